@@ -13,6 +13,11 @@ layout(binding=0) uniform GlobalUniforms {
 	mat4 u_projection;
 };
 
+layout(binding=1) uniform AtlasUniforms {
+	uvec2 u_atlas_cell_size;
+	uint u_atlas_columns;
+};
+
 layout(binding=0) uniform sampler2D u_texture;
 
 out vec4 v_color;
@@ -26,9 +31,13 @@ void main() {
 	uint texture_index = bitfieldExtract(vertex.uv_and_index, 16, 16);
 
 	ivec2 image_size = textureSize(u_texture, 0);
-	vec2 cell_size = vec2(128.0) / vec2(image_size);
+	vec2 cell_size = vec2(u_atlas_cell_size) / vec2(image_size);
+
+	// Rows are counted from the top of the image.
+	vec2 cell = vec2(texture_index % u_atlas_columns, texture_index / u_atlas_columns);
+	vec2 cell_offset = vec2(cell.x * cell_size.x, 1.0 - (cell.y + 1.0) * cell_size.y);
 
-	vec2 uv = base_uv * cell_size + vec2(cell_size.x * float(texture_index), 0.0);
+	vec2 uv = base_uv * cell_size + cell_offset;
 
 	gl_Position = u_projection * vec4(vertex.pos, 0.0, 1.0);
 	v_color = unpackUnorm4x8(vertex.byte_color);
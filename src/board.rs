@@ -2,11 +2,25 @@ use toybox::prelude::*;
 use crate::ext::*;
 use crate::map::*;
 
+use std::collections::VecDeque;
+use serde::{Serialize, Deserialize};
+
+
+/// Largest width or height accepted for boards described by other players. Well past anything the game
+/// offers, but small enough that a bad message can't allocate a huge board.
+pub const MAX_BOARD_SIZE: i32 = 100;
+
 
 #[derive(Debug)]
 pub struct Board {
 	pub types: Map<CellType>,
 	pub states: Map<CellState>,
+
+	/// The mine that ended the game, if any.
+	pub detonated: Option<Vec2i>,
+
+	/// Seeds bomb placement and relocation, so the same seed and actions always produce the same board.
+	seed: u64,
 }
 
 impl Board {
@@ -14,31 +28,77 @@ impl Board {
 		Self {
 			types: Map::new(size, CellType::Empty),
 			states: Map::new(size, CellState::Unopened),
+			detonated: None,
+			seed: 0,
 		}
 	}
 
-	pub fn with_bombs(size: Vec2i, count: usize) -> Self {
+	/// Places `count` bombs in distinct cells, or fills the board if there aren't enough cells.
+	pub fn with_bombs(size: Vec2i, count: usize, seed: u64) -> Self {
 		let mut board = Board::empty(size);
-        let mut rng = rand::thread_rng();
+		board.seed = seed;
+
+		let mut cells: Vec<Vec2i> = board.types.iter_with_positions().map(|(position, _)| position).collect();
+		let count = count.min(cells.len());
+		let mut rng = BoardRng::new(seed);
 
-        for _ in 0..count {
-            let pos = Vec2i::new(
-                rng.gen_range(0..size.x),
-                rng.gen_range(0..size.y)
-            );
+		// A partial Fisher-Yates shuffle, so no cell can be picked twice.
+		for index in 0..count {
+			let pick = index + rng.below(cells.len() - index);
+			cells.swap(index, pick);
+			board.types.set(cells[index], CellType::Bomb);
+		}
+
+		board.rebuild_adjacency();
+
+		board
+	}
+
+	/// Recreates a board with bombs in exactly the given positions.
+	pub fn from_bombs(size: Vec2i, bombs: &[Vec2i], seed: u64) -> Self {
+		let mut board = Board::empty(size);
+		board.seed = seed;
 
-            board.types.set(pos, CellType::Bomb);
-        }
+		for &position in bombs {
+			board.types.set(position, CellType::Bomb);
+		}
 
-        board.rebuild_adjacency();
+		board.rebuild_adjacency();
 
 		board
 	}
 
+	/// Covers every cell again, keeping the layout - including any bombs moved by a first click.
+	pub fn reset_states(&mut self) {
+		self.states.iter_mut().for_each(|state| *state = CellState::Unopened);
+		self.detonated = None;
+	}
+
+	/// How many safe cells have been opened, from 0 to 1.
+	pub fn cleared_fraction(&self) -> f32 {
+		let num_safe = self.types.iter().filter(|&&cell| cell != CellType::Bomb).count();
+		let num_opened = self.types.iter().zip(self.states.iter())
+			.filter(|&(&cell, &state)| cell != CellType::Bomb && state == CellState::Opened)
+			.count();
+
+		num_opened as f32 / num_safe.max(1) as f32
+	}
+
+	pub fn bomb_positions(&self) -> Vec<Vec2i> {
+		self.types.iter_with_positions()
+			.filter(|&(_, &cell)| cell == CellType::Bomb)
+			.map(|(position, _)| position)
+			.collect()
+	}
+
 	pub fn size(&self) -> Vec2i {
 		self.types.size()
 	}
 
+	pub fn seed(&self) -> u64 {
+		self.seed
+	}
+
 	fn rebuild_adjacency(&mut self) {
 		for pos in vec2i_range(self.size()) {
 			let Some(&cell) = self.types.get(pos) else { continue };
@@ -70,13 +130,22 @@ pub enum CellType {
 }
 
 
-#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CellState {
 	Unopened,
 	Flagged,
+	Questioned,
 	Opened,
 }
 
+impl CellState {
+	/// Whether the cell can be opened. Question marks are only a note to the player, so unlike flags
+	/// they don't protect a cell.
+	pub fn is_openable(&self) -> bool {
+		matches!(self, CellState::Unopened | CellState::Questioned)
+	}
+}
+
 
 
 
@@ -91,21 +160,130 @@ impl Board {
 			})
 	}
 
-	pub fn uncover_all(&mut self) {
-		for state in self.states.iter_mut() {
-			if *state != CellState::Flagged {
+	/// Whether `position` is an opened number with exactly that many flags around it, and still has
+	/// unopened neighbours to open.
+	pub fn can_chord(&self, position: Vec2i) -> bool {
+		if self.states.get(position) != Some(&CellState::Opened) {
+			return false
+		}
+
+		let Some(&CellType::BombAdjacent(count)) = self.types.get(position) else {
+			return false
+		};
+
+		let neighbour_states = || self.states.iter_neighbours(position);
+		let num_flags = neighbour_states().filter(|&&state| state == CellState::Flagged).count();
+		let has_unopened = neighbour_states().any(CellState::is_openable);
+
+		num_flags == count && has_unopened
+	}
+
+	/// The neighbours that would be opened by chording on `position`.
+	pub fn chord_targets(&self, position: Vec2i) -> Vec<Vec2i> {
+		iter_all_neighbour_positions(position, self.size())
+			.filter(|&neighbour| self.states.get(neighbour).map_or(false, CellState::is_openable))
+			.collect()
+	}
+
+	/// Whether `position` was flagged but isn't a mine. Only revealed once the game is lost.
+	pub fn is_wrong_flag(&self, position: Vec2i) -> bool {
+		self.detonated.is_some()
+			&& self.states.get(position) == Some(&CellState::Flagged)
+			&& self.types.get(position) != Some(&CellType::Bomb)
+	}
+
+	pub fn num_flags(&self) -> usize {
+		self.states.iter()
+			.filter(|&&state| state == CellState::Flagged)
+			.count()
+	}
+
+	pub fn num_bombs(&self) -> usize {
+		self.types.iter()
+			.filter(|&&cell| cell == CellType::Bomb)
+			.count()
+	}
+
+	/// The minimum number of clicks needed to clear the board, or its "3BV".
+	/// Follows the flood fill rules of this game, where openings only spread orthogonally.
+	pub fn three_bv(&self) -> usize {
+		self.count_three_bv(|_| true)
+	}
+
+	/// How much of the 3BV has been cleared so far.
+	pub fn three_bv_solved(&self) -> usize {
+		self.count_three_bv(|position| self.states.get(position) == Some(&CellState::Opened))
+	}
+
+	/// Counts openings with an empty cell that passes `filter`, and isolated numbers that pass `filter`.
+	fn count_three_bv(&self, filter: impl Fn(Vec2i) -> bool) -> usize {
+		let size = self.size();
+		let mut visited = Map::new(size, false);
+		let mut count = 0;
+
+		for (position, &cell) in self.types.iter_with_positions() {
+			if cell != CellType::Empty || *visited.get(position).unwrap() {
+				continue
+			}
+
+			// Flood the opening, marking the numbers it would reveal as visited too.
+			let mut counted = false;
+			let mut visit_queue = vec![position];
+			visited.set(position, true);
+
+			while let Some(position) = visit_queue.pop() {
+				counted |= filter(position);
+
+				for neighbour in iter_ortho_neighbour_positions(position, size) {
+					let neighbour_cell = *self.types.get(neighbour).unwrap();
+					if neighbour_cell == CellType::Bomb || *visited.get(neighbour).unwrap() {
+						continue
+					}
+
+					visited.set(neighbour, true);
+
+					if neighbour_cell == CellType::Empty {
+						visit_queue.push(neighbour);
+					}
+				}
+			}
+
+			if counted {
+				count += 1;
+			}
+		}
+
+		// Any numbers not revealed by an opening need a click each.
+		count + self.types.iter_with_positions()
+			.filter(|&(position, &cell)| matches!(cell, CellType::BombAdjacent(_)) && !*visited.get(position).unwrap())
+			.filter(|&(position, _)| filter(position))
+			.count()
+	}
+
+	/// Opens all non-flagged cells, returning the positions of any that weren't already open.
+	pub fn uncover_all(&mut self) -> Vec<Vec2i> {
+		let mut uncovered = Vec::new();
+
+		for (position, state) in self.states.iter_mut_with_positions() {
+			if state.is_openable() {
 				*state = CellState::Opened;
+				uncovered.push(position);
 			}
 		}
+
+		uncovered
 	}
 
-	pub fn flood_uncover_empty(&mut self, start: Vec2i) {
+	/// Opens cells reachable from `start`, returning each newly opened cell along with its
+	/// breadth-first distance from `start`.
+	pub fn flood_uncover_empty(&mut self, start: Vec2i) -> Vec<(Vec2i, usize)> {
 		let start_cell = *self.types.get(start).unwrap();
 		let starting_from_blank = start_cell == CellType::Empty;
 
-		let mut visit_queue = vec![start];
+		let mut opened = Vec::new();
+		let mut visit_queue = VecDeque::from([(start, 0)]);
 
-		while let Some(position) = visit_queue.pop() {
+		while let Some((position, distance)) = visit_queue.pop_front() {
 			for neighbour_position in iter_ortho_neighbour_positions(position, self.size()) {
 				let cell = *self.types.get(neighbour_position).unwrap();
 				if cell == CellType::Bomb {
@@ -113,52 +291,165 @@ impl Board {
 				}
 
 				let state = self.states.get_mut(neighbour_position).unwrap();
-				if *state != CellState::Unopened {
+				if !state.is_openable() {
 					continue;
 				}
 
 				*state = CellState::Opened;
+				opened.push((neighbour_position, distance + 1));
 
 				if starting_from_blank && cell == CellType::Empty {
-					visit_queue.push(neighbour_position);
+					visit_queue.push_back((neighbour_position, distance + 1));
 				}
 			}
 		}
+
+		opened
 	}
 
+	/// Moves a bomb to another empty cell, or leaves it where it is if there are none.
 	pub fn move_bomb(&mut self, position: Vec2i) {
 		println!("Moving bomb from {position:?}");
 
+		let candidates: Vec<Vec2i> = self.types.iter_with_positions()
+			.filter(|&(candidate, &cell)| candidate != position && cell == CellType::Empty)
+			.map(|(candidate, _)| candidate)
+			.collect();
+
+		if candidates.is_empty() {
+			return
+		}
+
+		// Derived from the position so that relocation is deterministic, whatever order things happen in.
+		let position_hash = (position.x as u64) << 32 | position.y as u64;
+		let mut rng = BoardRng::new(self.seed ^ position_hash.wrapping_mul(0x9e3779b97f4a7c15));
+
 		self.types.set(position, CellType::Empty);
+		self.types.set(candidates[rng.below(candidates.len())], CellType::Bomb);
 
-        let mut rng = rand::thread_rng();
-        let Vec2i{x: width, y: height} = self.size();
+		self.rebuild_adjacency();
+	}
+}
 
-        // Retry naively until it works
-        for _ in 0..32 {
-            let new_position = Vec2i::new(
-                rng.gen_range(0..width),
-                rng.gen_range(0..height)
-            );
 
-            if new_position == position {
-            	continue
-            }
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GameStatus {
+	Playing,
+	Lost,
+	Won,
+}
 
-            let cell = self.types.get_mut(new_position).unwrap();
-            if *cell == CellType::Empty {
-            	*cell = CellType::Bomb;
-            	break
-            }
-        }
+/// The whole board, for sending to another instance - co-op clients when they join or a new game starts,
+/// and spectators.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct BoardSnapshot {
+	pub seed: u64,
+	pub width: i32,
+	pub height: i32,
+	pub bombs: Vec<[i32; 2]>,
+	pub states: Vec<CellState>,
+	pub detonated: Option<[i32; 2]>,
+	pub status: GameStatus,
+}
 
-		self.rebuild_adjacency();
+impl BoardSnapshot {
+	pub fn new(board: &Board, status: GameStatus) -> BoardSnapshot {
+		let size = board.size();
+		let to_array = |position: Vec2i| [position.x, position.y];
+
+		BoardSnapshot {
+			seed: board.seed(),
+			width: size.x,
+			height: size.y,
+			bombs: board.bomb_positions().into_iter().map(to_array).collect(),
+			states: board.states.iter().copied().collect(),
+			detonated: board.detonated.map(to_array),
+			status,
+		}
+	}
+
+	/// Whether a board can be made from this. Snapshots come from other instances, so are checked first.
+	pub fn is_valid(&self) -> bool {
+		let valid_size = |size| (1..=MAX_BOARD_SIZE).contains(&size);
+		if !valid_size(self.width) || !valid_size(self.height) {
+			return false
+		}
+
+		let in_bounds = |&[x, y]: &[i32; 2]| (0..self.width).contains(&x) && (0..self.height).contains(&y);
 
-		// If the newly empty cell has no adjacent bombs, flood fill as normal
-		let new_cell = self.types.get(position).unwrap();
-		if new_cell == &CellType::Empty {
-			self.flood_uncover_empty(position);
+		self.states.len() == (self.width * self.height) as usize
+			&& self.bombs.iter().all(in_bounds)
+			&& self.detonated.iter().all(in_bounds)
+	}
+
+	pub fn make_board(&self) -> Board {
+		let from_array = |[x, y]: [i32; 2]| Vec2i::new(x, y);
+		let bombs: Vec<Vec2i> = self.bombs.iter().copied().map(from_array).collect();
+
+		let mut board = Board::from_bombs(Vec2i::new(self.width, self.height), &bombs, self.seed);
+		board.states.iter_mut().zip(&self.states).for_each(|(state, &snapshot)| *state = snapshot);
+		board.detonated = self.detonated.map(from_array);
+		board
+	}
+}
+
+
+/// SplitMix64. Spelled out here rather than taken from `rand`, whose `StdRng` algorithm may change between
+/// versions, because shared seeds, co-op and versus all need every build to make the same board from a seed.
+struct BoardRng(u64);
+
+impl BoardRng {
+	fn new(seed: u64) -> BoardRng {
+		BoardRng(seed)
+	}
+
+	fn next_u64(&mut self) -> u64 {
+		self.0 = self.0.wrapping_add(0x9e3779b97f4a7c15);
+
+		let mut z = self.0;
+		z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+		z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+		z ^ (z >> 31)
+	}
+
+	/// Uniform in `0..bound`. Values from the uneven top end of the range are rejected, so a plain modulo isn't biased.
+	fn below(&mut self, bound: usize) -> usize {
+		let bound = bound as u64;
+		let limit = u64::MAX - u64::MAX % bound;
+
+		loop {
+			let value = self.next_u64();
+			if value < limit {
+				return (value % bound) as usize
+			}
 		}
 	}
 }
 
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn positions(list: &[(i32, i32)]) -> Vec<Vec2i> {
+		list.iter().map(|&(x, y)| Vec2i::new(x, y)).collect()
+	}
+
+	/// Shared seeds only work if every build places mines identically, so this must never change.
+	#[test]
+	fn bomb_placement_is_pinned() {
+		let board = Board::with_bombs(Vec2i::new(8, 8), 10, 12345);
+
+		let expected = positions(&[(0, 0), (5, 0), (7, 0), (4, 1), (0, 4), (1, 4), (6, 5), (6, 6), (4, 7), (7, 7)]);
+		assert_eq!(board.bomb_positions(), expected);
+	}
+
+	#[test]
+	fn bombs_never_share_a_cell() {
+		let board = Board::with_bombs(Vec2i::new(3, 3), 20, 1);
+		assert_eq!(board.bomb_positions().len(), 9);
+
+		let board = Board::with_bombs(Vec2i::new(16, 16), 40, 99);
+		assert_eq!(board.bomb_positions().len(), 40);
+	}
+}
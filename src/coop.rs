@@ -0,0 +1,597 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::board::{Board, BoardSnapshot, CellState, GameStatus};
+use crate::net::{Connection, Listener};
+use crate::view::{CellResponse, open_response, chord_response};
+
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use std::time::{Duration, Instant};
+
+
+/// How often a client that lost its connection tries to get it back.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(2);
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ActionKind {
+	Open,
+	Flag,
+	Question,
+	/// Removes a flag or question mark.
+	Clear,
+	Chord,
+}
+
+/// Something a client wants to do to the shared board.
+///
+/// Actions are applied by the host in the order it receives them, and only if the cell is still in the state
+/// the player saw when they acted. So if two players flag the same cell at once, the first flag sticks and the
+/// second is dropped rather than removing it again, and a cell flagged by one player can't be opened by another
+/// who hadn't seen the flag yet.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct CoopAction {
+	pub kind: ActionKind,
+	pub position: [i32; 2],
+	pub expected: CellState,
+}
+
+impl CoopAction {
+	pub fn from_response(board: &Board, position: Vec2i, response: CellResponse) -> Option<CoopAction> {
+		let kind = match response {
+			CellResponse::BombHit | CellResponse::OpenSpaceUncovered | CellResponse::UnsafeSpaceUncovered => ActionKind::Open,
+			CellResponse::FlagPlaced => ActionKind::Flag,
+			CellResponse::QuestionPlaced => ActionKind::Question,
+			CellResponse::FlagRemoved | CellResponse::QuestionRemoved => ActionKind::Clear,
+			CellResponse::Chord => ActionKind::Chord,
+		};
+
+		Some(CoopAction {
+			kind,
+			position: to_array(position),
+			expected: *board.states.get(position)?,
+		})
+	}
+
+	/// Works out what the action does to the host's board, or None if the cell has changed since the player saw it.
+	pub fn response(&self, board: &Board) -> Option<(Vec2i, CellResponse)> {
+		let position = from_array(self.position);
+		if board.states.get(position) != Some(&self.expected) {
+			return None
+		}
+
+		let response = match (self.kind, self.expected) {
+			(ActionKind::Open, _) => open_response(board, position)?,
+			(ActionKind::Chord, _) => chord_response(board, position)?,
+			(ActionKind::Flag, CellState::Unopened) => CellResponse::FlagPlaced,
+			(ActionKind::Question, CellState::Flagged) => CellResponse::QuestionPlaced,
+			(ActionKind::Clear, CellState::Flagged) => CellResponse::FlagRemoved,
+			(ActionKind::Clear, CellState::Questioned) => CellResponse::QuestionRemoved,
+			_ => return None,
+		};
+
+		Some((position, response))
+	}
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct CellChange {
+	pub position: [i32; 2],
+	pub state: CellState,
+}
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct PlayerCursor {
+	pub player_id: u32,
+	pub position: Option<[i32; 2]>,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum CoopMessage {
+	/// Client to host, on connecting or reconnecting. Returning players send the token they were welcomed with,
+	/// which gets them their old player id back.
+	Hello {
+		name: String,
+		#[serde(default)]
+		token: Option<u64>,
+	},
+
+	/// Client to host.
+	Action(CoopAction),
+
+	/// Client to host, whenever the cell under their cursor changes.
+	Cursor { position: Option<[i32; 2]> },
+
+	/// Host to client, in response to `Hello`. Always followed by a snapshot.
+	/// `name` differs from the one sent if someone connected was already using it.
+	Welcome { player_id: u32, name: String, token: u64 },
+
+	/// Host to client.
+	Snapshot(BoardSnapshot),
+
+	/// Host to client. Cell states that changed since the last diff or snapshot.
+	Diff {
+		changes: Vec<CellChange>,
+
+		/// Set if the layout changed, which only happens when a first click moves a mine.
+		bombs: Option<Vec<[i32; 2]>>,
+		detonated: Option<[i32; 2]>,
+		status: GameStatus,
+	},
+
+	/// Host to client. Everyone's cursors, including the host's.
+	Cursors { cursors: Vec<PlayerCursor> },
+}
+
+type CoopConnection = Connection<CoopMessage, CoopMessage>;
+
+
+/// Something the game needs to react to.
+pub enum CoopEvent {
+	/// Host only. A client wants to change the board.
+	Action(CoopAction),
+
+	/// Client only. Replace the whole board.
+	Snapshot(BoardSnapshot),
+
+	/// Client only. Apply changes from the host.
+	Diff {
+		changes: Vec<(Vec2i, CellState)>,
+		bombs: Option<Vec<Vec2i>>,
+		detonated: Option<Vec2i>,
+		status: GameStatus,
+	},
+}
+
+
+/// Everyone who has joined a hosted game, indexed by player id.
+struct KnownPlayer {
+	name: String,
+
+	/// Secret handed to the player, so only they can reclaim their id. None for the host.
+	token: Option<u64>,
+}
+
+struct RemotePlayer {
+	player_id: u32,
+	name: String,
+	connection: CoopConnection,
+
+	/// Set on joining, and for everyone when a new game starts.
+	needs_snapshot: bool,
+}
+
+enum Role {
+	Host {
+		listener: Listener,
+		clients: Vec<RemotePlayer>,
+
+		/// Everyone who has ever joined, so reconnecting players keep their id and colour.
+		known_players: Vec<KnownPlayer>,
+
+		/// What clients are known to have been told, so only changes need to be sent.
+		synced_generation: Option<u32>,
+		synced_states: Vec<CellState>,
+		synced_bombs: Vec<Vec2i>,
+		synced_detonated: Option<Vec2i>,
+		synced_status: GameStatus,
+	},
+
+	Client {
+		address: String,
+		connection: Option<CoopConnection>,
+
+		/// Connecting can take a while, so reconnecting happens on another thread.
+		pending_connection: Option<Receiver<anyhow::Result<CoopConnection>>>,
+		last_connect_attempt: Instant,
+
+		/// From the host's `Welcome`, to be sent back when reconnecting.
+		token: Option<u64>,
+	},
+}
+
+
+pub struct CoopSession {
+	role: Role,
+
+	pub name: String,
+	pub player_id: u32,
+
+	/// Everyone's cursors, including this player's.
+	pub cursors: Vec<PlayerCursor>,
+	sent_cursor: Option<Option<[i32; 2]>>,
+}
+
+impl CoopSession {
+	pub const HOST_PLAYER_ID: u32 = 0;
+
+	pub fn host(port: u16, name: String) -> anyhow::Result<CoopSession> {
+		let listener = Listener::bind(("0.0.0.0", port))?;
+
+		if let Some(address) = listener.local_addr() {
+			println!("[coop] Hosting on {address}");
+		}
+
+		Ok(CoopSession {
+			role: Role::Host {
+				listener,
+				clients: Vec::new(),
+				known_players: vec![KnownPlayer { name: name.clone(), token: None }],
+
+				synced_generation: None,
+				synced_states: Vec::new(),
+				synced_bombs: Vec::new(),
+				synced_detonated: None,
+				synced_status: GameStatus::Playing,
+			},
+
+			name,
+			player_id: Self::HOST_PLAYER_ID,
+			cursors: Vec::new(),
+			sent_cursor: None,
+		})
+	}
+
+	pub fn join(address: &str, name: String) -> anyhow::Result<CoopSession> {
+		let connection = CoopConnection::connect(address)?;
+		connection.send(CoopMessage::Hello { name: name.clone(), token: None });
+
+		println!("[coop] Joined {}", connection.peer());
+
+		Ok(CoopSession {
+			role: Role::Client {
+				address: address.to_owned(),
+				connection: Some(connection),
+				pending_connection: None,
+				last_connect_attempt: Instant::now(),
+				token: None,
+			},
+
+			name,
+			player_id: u32::MAX,
+			cursors: Vec::new(),
+			sent_cursor: None,
+		})
+	}
+
+	pub fn is_host(&self) -> bool {
+		matches!(self.role, Role::Host{..})
+	}
+
+	pub fn is_connected(&self) -> bool {
+		match &self.role {
+			Role::Host{..} => true,
+			Role::Client { connection, .. } => connection.as_ref().is_some_and(CoopConnection::is_connected),
+		}
+	}
+
+	pub fn num_players(&self) -> usize {
+		match &self.role {
+			Role::Host { clients, .. } => clients.len() + 1,
+			Role::Client{..} => self.cursors.len(),
+		}
+	}
+
+	/// Client only. Asks the host to apply an action.
+	pub fn send_action(&self, action: CoopAction) {
+		if let Role::Client { connection: Some(connection), .. } = &self.role {
+			connection.send(CoopMessage::Action(action));
+		}
+	}
+
+	/// Handles connections and incoming messages, returning anything the game needs to act on.
+	pub fn update(&mut self) -> Vec<CoopEvent> {
+		let mut events = Vec::new();
+
+		match &mut self.role {
+			Role::Host { listener, clients, known_players, .. } => {
+				while let Some(connection) = listener.accept() {
+					println!("[coop] {} connected", connection.peer());
+
+					// Player ids are assigned once we know who it is.
+					clients.push(RemotePlayer {
+						player_id: u32::MAX,
+						name: String::new(),
+						connection,
+						needs_snapshot: false,
+					});
+				}
+
+				let mut cursors_changed = false;
+
+				// Older connections for players who have reconnected, which may not have noticed they're gone yet.
+				let mut superseded = Vec::new();
+
+				for index in 0..clients.len() {
+					for message in clients[index].connection.recv_all() {
+						let client_id = clients[index].player_id;
+
+						match message {
+							CoopMessage::Hello { name, token } if client_id == u32::MAX => {
+								let returning_id = token
+									.and_then(|token| known_players.iter().position(|known| known.token == Some(token)));
+
+								let (player_id, name, token) = match returning_id {
+									Some(player_id) => {
+										let player_id = player_id as u32;
+										superseded.extend(clients.iter().enumerate()
+											.filter(|&(other, client)| other != index && client.player_id == player_id)
+											.map(|(other, _)| other));
+
+										(player_id, known_players[player_id as usize].name.clone(), token.unwrap_or_default())
+									}
+
+									None => {
+										let name = unique_name(name, &self.name, clients);
+										let token = rand::random();
+										known_players.push(KnownPlayer { name: name.clone(), token: Some(token) });
+										(known_players.len() as u32 - 1, name, token)
+									}
+								};
+
+								println!("[coop] '{name}' is player {player_id}");
+
+								let client = &mut clients[index];
+								client.player_id = player_id;
+								client.name = name.clone();
+								client.needs_snapshot = true;
+								client.connection.send(CoopMessage::Welcome { player_id, name, token });
+
+								// Catch them up with everyone's cursors.
+								cursors_changed = true;
+							}
+
+							CoopMessage::Action(action) if client_id != u32::MAX => {
+								events.push(CoopEvent::Action(action));
+							}
+
+							CoopMessage::Cursor { position } if client_id != u32::MAX => {
+								upsert_cursor(&mut self.cursors, PlayerCursor { player_id: client_id, position });
+								cursors_changed = true;
+							}
+
+							_ => {}
+						}
+					}
+				}
+
+				let mut index = 0;
+				clients.retain(|client| {
+					let is_superseded = superseded.contains(&index);
+					index += 1;
+
+					if is_superseded {
+						println!("[coop] '{}' reconnected, dropping their old connection", client.name);
+						return false
+					}
+
+					let connected = client.connection.is_connected();
+					if !connected {
+						println!("[coop] '{}' disconnected", client.name);
+					}
+
+					connected
+				});
+
+				let connected_ids: Vec<u32> = clients.iter().map(|client| client.player_id).collect();
+				let num_cursors = self.cursors.len();
+				self.cursors.retain(|cursor| cursor.player_id == Self::HOST_PLAYER_ID || connected_ids.contains(&cursor.player_id));
+				cursors_changed |= num_cursors != self.cursors.len();
+
+				if cursors_changed {
+					broadcast(clients, CoopMessage::Cursors { cursors: self.cursors.clone() });
+				}
+			}
+
+			Role::Client { address, connection, pending_connection, last_connect_attempt, token } => {
+				if !connection.as_ref().is_some_and(CoopConnection::is_connected)
+					&& pending_connection.is_none()
+					&& last_connect_attempt.elapsed() > RECONNECT_INTERVAL
+				{
+					*last_connect_attempt = Instant::now();
+					*pending_connection = start_connecting(address.clone());
+				}
+
+				if let Some(receiver) = pending_connection {
+					match receiver.try_recv() {
+						Ok(Ok(new_connection)) => {
+							println!("[coop] Reconnected to {address}");
+							new_connection.send(CoopMessage::Hello { name: self.name.clone(), token: *token });
+							*connection = Some(new_connection);
+							*pending_connection = None;
+							self.sent_cursor = None;
+						}
+
+						Ok(Err(error)) => {
+							println!("[coop] Failed to reconnect to {address}: {error}");
+							*pending_connection = None;
+						}
+
+						Err(TryRecvError::Empty) => {}
+						Err(TryRecvError::Disconnected) => *pending_connection = None,
+					}
+				}
+
+				let Some(connection) = connection else { return events };
+
+				for message in connection.recv_all() {
+					match message {
+						CoopMessage::Welcome { player_id, name, token: new_token } => {
+							self.player_id = player_id;
+							self.name = name;
+							*token = Some(new_token);
+						}
+
+						CoopMessage::Snapshot(snapshot) if snapshot.is_valid() => events.push(CoopEvent::Snapshot(snapshot)),
+						CoopMessage::Snapshot(_) => println!("[coop] Ignoring invalid snapshot"),
+
+						CoopMessage::Diff { changes, bombs, detonated, status } => {
+							events.push(CoopEvent::Diff {
+								changes: changes.into_iter().map(|change| (from_array(change.position), change.state)).collect(),
+								bombs: bombs.map(|bombs| bombs.into_iter().map(from_array).collect()),
+								detonated: detonated.map(from_array),
+								status,
+							});
+						}
+
+						CoopMessage::Cursors { cursors } => self.cursors = cursors,
+						_ => {}
+					}
+				}
+			}
+		}
+
+		events
+	}
+
+	/// Host only. Sends clients whatever has changed on the board since the last sync, and full snapshots to anyone
+	/// who has just joined. `generation` should change whenever a new game starts, which sends everyone a snapshot.
+	pub fn sync_board(&mut self, board: &Board, status: GameStatus, generation: u32) {
+		let Role::Host { clients, synced_generation, synced_states, synced_bombs, synced_detonated, synced_status, .. } = &mut self.role else {
+			return
+		};
+
+		let bombs = board.bomb_positions();
+
+		if *synced_generation != Some(generation) {
+			clients.iter_mut().for_each(|client| client.needs_snapshot = true);
+		} else {
+			let changes: Vec<CellChange> = board.states.iter_with_positions()
+				.zip(synced_states.iter())
+				.filter(|&((_, state), synced)| state != synced)
+				.map(|((position, &state), _)| CellChange { position: to_array(position), state })
+				.collect();
+
+			let bombs_changed = bombs != *synced_bombs;
+			let anything_changed = !changes.is_empty() || bombs_changed
+				|| board.detonated != *synced_detonated || status != *synced_status;
+
+			if anything_changed {
+				let message = CoopMessage::Diff {
+					changes,
+					bombs: bombs_changed.then(|| bombs.iter().copied().map(to_array).collect()),
+					detonated: board.detonated.map(to_array),
+					status,
+				};
+
+				for client in clients.iter().filter(|client| !client.needs_snapshot) {
+					send_to_player(client, message.clone());
+				}
+			}
+		}
+
+		if clients.iter().any(|client| client.needs_snapshot && client.player_id != u32::MAX) {
+			let snapshot = BoardSnapshot::new(board, status);
+
+			for client in clients.iter_mut().filter(|client| client.needs_snapshot && client.player_id != u32::MAX) {
+				client.connection.send(CoopMessage::Snapshot(snapshot.clone()));
+				client.needs_snapshot = false;
+			}
+		}
+
+		*synced_generation = Some(generation);
+		*synced_states = board.states.iter().copied().collect();
+		*synced_bombs = bombs;
+		*synced_detonated = board.detonated;
+		*synced_status = status;
+	}
+
+	/// Shares this player's cursor with everyone else, if it's moved.
+	pub fn send_cursor(&mut self, position: Option<Vec2i>) {
+		let position = position.map(to_array);
+		if self.sent_cursor == Some(position) {
+			return
+		}
+
+		self.sent_cursor = Some(position);
+
+		match &self.role {
+			Role::Host { clients, .. } => {
+				upsert_cursor(&mut self.cursors, PlayerCursor { player_id: self.player_id, position });
+				broadcast(clients, CoopMessage::Cursors { cursors: self.cursors.clone() });
+			}
+
+			Role::Client { connection: Some(connection), .. } => connection.send(CoopMessage::Cursor { position }),
+			Role::Client { connection: None, .. } => {}
+		}
+	}
+
+	/// Everyone else's cursors, with the player index used for their colour.
+	pub fn remote_cursors(&self) -> Vec<(Vec2i, usize)> {
+		self.cursors.iter()
+			.filter(|cursor| cursor.player_id != self.player_id)
+			.filter_map(|cursor| Some((from_array(cursor.position?), cursor.player_id as usize)))
+			.collect()
+	}
+}
+
+/// Connects on another thread, since an unreachable host can take a while to give up on.
+fn start_connecting(address: String) -> Option<Receiver<anyhow::Result<CoopConnection>>> {
+	let (sender, receiver) = channel();
+
+	let result = std::thread::Builder::new()
+		.name("coop connect".into())
+		.spawn(move || {
+			let _ = sender.send(CoopConnection::connect(address.as_str()));
+		});
+
+	match result {
+		Ok(_) => Some(receiver),
+		Err(error) => {
+			println!("[coop] Failed to start connecting: {error}");
+			None
+		}
+	}
+}
+
+/// Numbers names that are already in use by someone connected, so players can be told apart
+/// and nobody can pass themselves off as someone else.
+fn unique_name(name: String, host_name: &str, clients: &[RemotePlayer]) -> String {
+	let in_use = |candidate: &str| candidate == host_name
+		|| clients.iter().any(|client| client.player_id != u32::MAX && client.name == candidate);
+
+	if !in_use(&name) {
+		return name
+	}
+
+	let mut number = 2;
+	loop {
+		let candidate = format!("{name} ({number})");
+		if !in_use(&candidate) {
+			return candidate
+		}
+
+		number += 1;
+	}
+}
+
+fn broadcast(clients: &[RemotePlayer], message: CoopMessage) {
+	for client in clients.iter() {
+		send_to_player(client, message.clone());
+	}
+}
+
+/// Clients that haven't said hello yet are caught up with a snapshot once they do.
+fn send_to_player(client: &RemotePlayer, message: CoopMessage) {
+	if client.player_id != u32::MAX {
+		client.connection.send(message);
+	}
+}
+
+fn upsert_cursor(cursors: &mut Vec<PlayerCursor>, cursor: PlayerCursor) {
+	match cursors.iter_mut().find(|existing| existing.player_id == cursor.player_id) {
+		Some(existing) => *existing = cursor,
+		None => cursors.push(cursor),
+	}
+}
+
+fn to_array(position: Vec2i) -> [i32; 2] {
+	[position.x, position.y]
+}
+
+fn from_array([x, y]: [i32; 2]) -> Vec2i {
+	Vec2i::new(x, y)
+}
@@ -0,0 +1,181 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::board::{Board, CellType, CellState};
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+
+/// A board everyone gets on the same (UTC) day, derived purely from the date so it works offline.
+#[derive(Debug, Clone)]
+pub struct DailyChallenge {
+	/// Formatted as YYYY-MM-DD.
+	pub date: String,
+	pub seed: u64,
+	pub board_size: Vec2i,
+	pub num_bombs: usize,
+}
+
+impl DailyChallenge {
+	pub fn today() -> DailyChallenge {
+		let days_since_epoch = SystemTime::now().duration_since(UNIX_EPOCH)
+			.map(|duration| duration.as_secs() / 86400)
+			.unwrap_or(0);
+
+		let (year, month, day) = civil_from_days(days_since_epoch as i64);
+		DailyChallenge::for_date(&format!("{year:04}-{month:02}-{day:02}"))
+	}
+
+	pub fn for_date(date: &str) -> DailyChallenge {
+		// std's hashers aren't guaranteed stable between releases, and everyone needs the same board.
+		let seed = fnv1a(date.as_bytes());
+
+		// Vary the board a little from day to day, from around beginner up to intermediate.
+		let width = 9 + (seed % 8) as i32;
+		let height = 9 + ((seed >> 8) % 8) as i32;
+		let density = 0.12 + ((seed >> 16) % 6) as f32 * 0.01;
+		let num_bombs = ((width * height) as f32 * density).round() as usize;
+
+		DailyChallenge {
+			date: date.to_owned(),
+			seed,
+			board_size: Vec2i::new(width, height),
+			num_bombs,
+		}
+	}
+
+	pub fn make_board(&self) -> Board {
+		Board::with_bombs(self.board_size, self.num_bombs, self.seed)
+	}
+}
+
+
+/// The one scored attempt at a daily challenge.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DailyResult {
+	pub date: String,
+
+	/// Attempts are recorded as soon as they start, so abandoning a bad start doesn't earn a second try.
+	pub finished: bool,
+	pub won: bool,
+	pub seconds: f32,
+	pub three_bv: usize,
+	pub three_bv_solved: usize,
+
+	/// Rows of the final board as emoji, as it appeared when the game ended.
+	pub grid: Vec<String>,
+}
+
+impl DailyResult {
+	pub fn started(date: &str) -> DailyResult {
+		DailyResult {
+			date: date.to_owned(),
+			finished: false,
+			won: false,
+			seconds: 0.0,
+			three_bv: 0,
+			three_bv_solved: 0,
+			grid: Vec::new(),
+		}
+	}
+
+	pub fn three_bv_per_second(&self) -> f32 {
+		if self.seconds > 0.0 {
+			self.three_bv_solved as f32 / self.seconds
+		} else {
+			0.0
+		}
+	}
+
+	/// A short summary suitable for pasting into chat.
+	pub fn share_text(&self) -> String {
+		let outcome = match (self.finished, self.won) {
+			(false, _) => "💤",
+			(true, true) => "🏆",
+			(true, false) => "💥",
+		};
+
+		let mut text = format!("Myneswooper daily {} {outcome}\n", self.date);
+		text += &format!("⏱ {:.2}s  3BV {}/{}  3BV/s {:.2}\n",
+			self.seconds, self.three_bv_solved, self.three_bv, self.three_bv_per_second());
+
+		for row in &self.grid {
+			text += row;
+			text.push('\n');
+		}
+
+		text
+	}
+}
+
+
+/// Renders the board as rows of emoji, top row first.
+pub fn emoji_grid(board: &Board) -> Vec<String> {
+	let size = board.size();
+
+	(0..size.y).rev()
+		.map(|y| {
+			(0..size.x)
+				.map(|x| {
+					let position = Vec2i::new(x, y);
+					let cell_type = board.types.get(position).copied();
+					let cell_state = board.states.get(position).copied();
+
+					match (cell_state, cell_type) {
+						_ if board.detonated == Some(position) => '💥',
+						(Some(CellState::Flagged), Some(CellType::Bomb)) => '🚩',
+						(Some(CellState::Flagged), _) => '❌',
+						(Some(CellState::Opened), _) => '🟩',
+						_ => '⬛',
+					}
+				})
+				.collect()
+		})
+		.collect()
+}
+
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+	bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+		(hash ^ byte as u64).wrapping_mul(0x100000001b3)
+	})
+}
+
+/// Converts days since 1970-01-01 to a (year, month, day) in the proleptic Gregorian calendar.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+	let z = days + 719468;
+	let era = z.div_euclid(146097);
+	let day_of_era = z.rem_euclid(146097);
+	let year_of_era = (day_of_era - day_of_era/1460 + day_of_era/36524 - day_of_era/146096) / 365;
+	let day_of_year = day_of_era - (365*year_of_era + year_of_era/4 - year_of_era/100);
+	let mp = (5*day_of_year + 2) / 153;
+	let day = (day_of_year - (153*mp + 2)/5 + 1) as u32;
+	let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u32;
+	let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+	(year, month, day)
+}
+
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	/// Everyone playing on the same day has to get the same board, whatever build they're on.
+	#[test]
+	fn daily_board_is_pinned() {
+		let daily = DailyChallenge::for_date("2024-03-14");
+
+		assert_eq!(daily.seed, 0xd7950e89c2c48c9d);
+		assert_eq!(daily.board_size, Vec2i::new(14, 13));
+		assert_eq!(daily.num_bombs, 25);
+
+		let expected: Vec<Vec2i> = [
+			(13, 0), (11, 1), (13, 1), (3, 3), (6, 3), (7, 3), (10, 3), (1, 4), (2, 4), (5, 4), (13, 5), (4, 6), (11, 6),
+			(12, 6), (5, 7), (3, 8), (12, 8), (4, 9), (7, 9), (7, 10), (6, 11), (11, 11), (0, 12), (3, 12), (10, 12),
+		].iter().map(|&(x, y)| Vec2i::new(x, y)).collect();
+
+		assert_eq!(daily.make_board().bomb_positions(), expected);
+	}
+}
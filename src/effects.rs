@@ -0,0 +1,221 @@
+use toybox::prelude::*;
+use rand::{SeedableRng, rngs::StdRng};
+
+use crate::ext::*;
+use crate::quad_builder::QuadBuilder;
+use crate::view::AtlasBinding;
+
+
+/// Effects are simulated in fixed steps so that the same seed always produces the same result,
+/// regardless of framerate.
+const TIMESTEP: f32 = 1.0 / 120.0;
+
+const GRAVITY: f32 = -3.0;
+
+
+struct Particle {
+	position: Vec2,
+	velocity: Vec2,
+	gravity: f32,
+	drag: f32,
+
+	size: f32,
+	growth: f32,
+	color: Color,
+
+	age: f32,
+	lifetime: f32,
+}
+
+
+pub struct Effects {
+	main_vs: gfx::ShaderHandle,
+	main_fs: gfx::ShaderHandle,
+
+	rng: StdRng,
+	particles: Vec<Particle>,
+	builder: QuadBuilder,
+
+	time_accumulator: f32,
+
+	trauma: f32,
+	shake_time: f32,
+	shake_seed: f32,
+}
+
+impl Effects {
+	pub fn new(ctx: &mut toybox::Context, seed: u64) -> anyhow::Result<Effects> {
+		let rm = &mut ctx.gfx.resource_manager;
+
+		Ok(Effects {
+			main_vs: rm.request(gfx::LoadShaderRequest::from("shaders/main.vs.glsl")?),
+			main_fs: rm.request(gfx::LoadShaderRequest::from("shaders/main.fs.glsl")?),
+
+			rng: StdRng::seed_from_u64(seed),
+			particles: Vec::new(),
+			builder: QuadBuilder::default(),
+
+			time_accumulator: 0.0,
+
+			trauma: 0.0,
+			shake_time: 0.0,
+			shake_seed: 0.0,
+		})
+	}
+
+	pub fn reset(&mut self, seed: u64) {
+		self.rng = StdRng::seed_from_u64(seed);
+		self.particles.clear();
+		self.time_accumulator = 0.0;
+		self.trauma = 0.0;
+	}
+
+	pub fn add_shake(&mut self, amount: f32) {
+		self.trauma = (self.trauma + amount).min(1.0);
+		self.shake_seed = self.rng.gen_range(0.0..100.0);
+	}
+
+	/// Debris and a flash for a detonated mine.
+	pub fn explode(&mut self, position: Vec2, cell_size: f32) {
+		self.particles.push(Particle {
+			position,
+			velocity: Vec2::zero(),
+			gravity: 0.0,
+			drag: 0.0,
+
+			size: cell_size,
+			growth: cell_size * 12.0,
+			color: Color::rgb(1.0, 0.9, 0.6),
+
+			age: 0.0,
+			lifetime: 0.2,
+		});
+
+		for _ in 0..48 {
+			let angle = self.rng.gen_range(0.0..TAU);
+			let speed = self.rng.gen_range(0.5..2.5);
+			let brightness = self.rng.gen_range(0.1..0.5);
+
+			self.particles.push(Particle {
+				position,
+				velocity: Vec2::from_angle(angle) * speed,
+				gravity: GRAVITY,
+				drag: 2.0,
+
+				size: cell_size * self.rng.gen_range(0.05..0.2),
+				growth: 0.0,
+				color: Color::rgb(brightness * 2.0, brightness, brightness * 0.5),
+
+				age: 0.0,
+				lifetime: self.rng.gen_range(0.4..1.2),
+			});
+		}
+
+		self.add_shake(0.8);
+	}
+
+	/// Confetti bursting up out of the board.
+	pub fn confetti(&mut self, bounds: Aabb2) {
+		let palette = [
+			Color::rgb(1.0, 0.3, 0.3),
+			Color::rgb(1.0, 0.8, 0.2),
+			Color::rgb(0.3, 1.0, 0.4),
+			Color::rgb(0.3, 0.6, 1.0),
+			Color::rgb(0.9, 0.4, 1.0),
+		];
+
+		let cell_size = bounds.size().y / 16.0;
+
+		for _ in 0..160 {
+			let position = Vec2::new(
+				self.rng.gen_range(bounds.min.x..bounds.max.x),
+				self.rng.gen_range(bounds.min.y..bounds.max.y),
+			);
+
+			let velocity = Vec2::new(self.rng.gen_range(-0.8..0.8), self.rng.gen_range(1.0..3.0));
+			let color = palette[self.rng.gen_range(0..palette.len())];
+
+			self.particles.push(Particle {
+				position,
+				velocity,
+				gravity: GRAVITY,
+				drag: 1.5,
+
+				size: cell_size * self.rng.gen_range(0.2..0.4),
+				growth: 0.0,
+				color,
+
+				age: 0.0,
+				lifetime: self.rng.gen_range(1.0..2.5),
+			});
+		}
+
+		self.add_shake(0.3);
+	}
+
+	pub fn update(&mut self, dt: f32) {
+		// Avoid a death spiral after long stalls.
+		self.time_accumulator = (self.time_accumulator + dt).min(0.25);
+
+		while self.time_accumulator >= TIMESTEP {
+			self.time_accumulator -= TIMESTEP;
+			self.step();
+		}
+	}
+
+	fn step(&mut self) {
+		for particle in self.particles.iter_mut() {
+			particle.velocity.y += particle.gravity * TIMESTEP;
+			particle.velocity = particle.velocity * (1.0 - (particle.drag * TIMESTEP).min(1.0));
+			particle.position = particle.position + particle.velocity * TIMESTEP;
+			particle.size += particle.growth * TIMESTEP;
+			particle.age += TIMESTEP;
+		}
+
+		self.particles.retain(|particle| particle.age < particle.lifetime);
+
+		self.trauma = (self.trauma - TIMESTEP * 1.5).max(0.0);
+		self.shake_time += TIMESTEP;
+	}
+
+	/// Offset to apply to the camera for screen shake.
+	pub fn shake_offset(&self) -> Vec2 {
+		let strength = self.trauma.powi(2) * 0.08;
+		let t = (self.shake_time + self.shake_seed) * 40.0;
+
+		Vec2::new(
+			(t * 1.1).sin() + (t * 2.3).sin() * 0.5,
+			(t * 1.7).sin() + (t * 2.9).sin() * 0.5,
+		) * strength
+	}
+
+	pub fn draw(&mut self, gfx: &mut gfx::System, atlas: &AtlasBinding) {
+		if self.particles.is_empty() {
+			return
+		}
+
+		self.builder.clear();
+
+		for particle in self.particles.iter() {
+			let fade = 1.0 - particle.age / particle.lifetime;
+			let bounds = Aabb2::around_point(particle.position, Vec2::splat(particle.size / 2.0));
+			self.builder.add(bounds, particle.color.with_alpha(fade), atlas.blank_glyph);
+		}
+
+		self.builder.finish();
+
+		let mut group = gfx.frame_encoder.command_group("effects");
+
+		group.draw(self.main_vs, self.main_fs)
+			.elements(self.builder.indices.len() as u32)
+			.indexed(&self.builder.indices)
+			.ssbo(0, &self.builder.vertices)
+			.ubo(1, &[atlas.uniforms])
+			.sampled_image(0, atlas.image, atlas.sampler)
+			.depth_test(false)
+			.blend_mode(gfx::BlendMode::ALPHA);
+	}
+}
+
+
+use std::f32::consts::TAU;
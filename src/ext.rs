@@ -10,6 +10,7 @@ pub trait Aabb2Ext {
 	}
 
 	fn scale_about_center(&self, amount: Vec2) -> Aabb2;
+	fn translate(&self, offset: Vec2) -> Aabb2;
 	fn aspect(&self) -> f32 {
 		let size = self.size();
 		size.x / size.y
@@ -37,6 +38,13 @@ impl Aabb2Ext for Aabb2 {
 		Aabb2::around_point(center, extents)
 	}
 
+	fn translate(&self, offset: Vec2) -> Self {
+		Aabb2 {
+			min: self.min + offset,
+			max: self.max + offset,
+		}
+	}
+
 	fn size(&self) -> Vec2 {
 		if self.is_empty() {
 			Vec2::zero()
@@ -84,6 +92,18 @@ impl Aabb2Ext for Aabb2 {
 }
 
 
+pub trait ColorExt {
+	fn with_alpha(&self, alpha: f32) -> Color;
+}
+
+impl ColorExt for Color {
+	fn with_alpha(&self, alpha: f32) -> Color {
+		Color { a: self.a * alpha, ..*self }
+	}
+}
+
+
+
 pub fn vec2i_range(Vec2i{x, y}: Vec2i) -> impl Iterator<Item=Vec2i> {
 	(0..y)
 		.flat_map(move |j| {
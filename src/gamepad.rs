@@ -0,0 +1,177 @@
+use toybox::prelude::*;
+use gilrs::{Gilrs, GamepadId, Button, Axis, EventType};
+use gilrs::ff;
+
+use std::time::Duration;
+
+
+const STICK_DEADZONE: f32 = 0.5;
+
+/// How long a direction must be held before it starts repeating.
+const REPEAT_DELAY: f32 = 0.35;
+const REPEAT_INTERVAL_START: f32 = 0.15;
+const REPEAT_INTERVAL_MIN: f32 = 0.04;
+
+/// How much the repeat interval shrinks after each repeat.
+const REPEAT_ACCELERATION: f32 = 0.85;
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GamepadAction {
+	Move(Vec2i),
+	Open,
+	Flag,
+	Chord,
+	Back,
+	Menu,
+}
+
+
+pub struct Gamepad {
+	// A second instance alongside the one toybox's input system keeps. Gamepad events aren't read through
+	// `ctx.input` yet, so this is a stopgap until toybox exposes them - until then it can be turned off with
+	// MYNESWOOPER_NO_GAMEPAD if the two ever fight over a device.
+	gilrs: Option<Gilrs>,
+	active_gamepad: Option<GamepadId>,
+
+	held_direction: Vec2i,
+	repeat_timer: f32,
+	repeat_interval: f32,
+
+	// Effects stop playing when dropped, so the most recent one is kept around.
+	rumble_effect: Option<ff::Effect>,
+}
+
+impl Gamepad {
+	pub fn new() -> Gamepad {
+		let gilrs = if std::env::var_os("MYNESWOOPER_NO_GAMEPAD").is_some() {
+			println!("[gamepad] Gamepad support disabled");
+			None
+		} else {
+			match Gilrs::new() {
+				Ok(gilrs) => {
+					println!("[gamepad] Using a separate gilrs instance from toybox's input system");
+					Some(gilrs)
+				}
+
+				Err(error) => {
+					println!("[gamepad] Failed to initialise gamepad support: {error}");
+					None
+				}
+			}
+		};
+
+		Gamepad {
+			gilrs,
+			active_gamepad: None,
+
+			held_direction: Vec2i::zero(),
+			repeat_timer: 0.0,
+			repeat_interval: REPEAT_INTERVAL_START,
+
+			rumble_effect: None,
+		}
+	}
+
+	pub fn update(&mut self, dt: f32) -> Vec<GamepadAction> {
+		let mut actions = Vec::new();
+
+		let Some(gilrs) = &mut self.gilrs else {
+			return actions
+		};
+
+		while let Some(gilrs::Event{id, event, ..}) = gilrs.next_event() {
+			let EventType::ButtonPressed(button, _) = event else { continue };
+
+			self.active_gamepad = Some(id);
+
+			let action = match button {
+				Button::South => GamepadAction::Open,
+				Button::West => GamepadAction::Flag,
+				Button::North => GamepadAction::Chord,
+				Button::East => GamepadAction::Back,
+				Button::Start => GamepadAction::Menu,
+				_ => continue,
+			};
+
+			actions.push(action);
+		}
+
+		let direction = self.active_gamepad
+			.and_then(|id| gilrs.connected_gamepad(id))
+			.map(|gamepad| {
+				let dpad = |button, delta| if gamepad.is_pressed(button) { delta } else { Vec2i::zero() };
+				let stick = |axis| {
+					let value = gamepad.value(axis);
+					if value.abs() < STICK_DEADZONE { 0 } else { value.signum() as i32 }
+				};
+
+				let dpad_direction = dpad(Button::DPadUp, Vec2i::new(0, 1))
+					+ dpad(Button::DPadDown, Vec2i::new(0, -1))
+					+ dpad(Button::DPadLeft, Vec2i::new(-1, 0))
+					+ dpad(Button::DPadRight, Vec2i::new(1, 0));
+
+				match dpad_direction == Vec2i::zero() {
+					true => Vec2i::new(stick(Axis::LeftStickX), stick(Axis::LeftStickY)),
+					false => dpad_direction,
+				}
+			})
+			.unwrap_or(Vec2i::zero());
+
+		if direction != self.held_direction {
+			self.held_direction = direction;
+			self.repeat_timer = REPEAT_DELAY;
+			self.repeat_interval = REPEAT_INTERVAL_START;
+
+			if direction != Vec2i::zero() {
+				actions.push(GamepadAction::Move(direction));
+			}
+
+		} else if direction != Vec2i::zero() {
+			self.repeat_timer -= dt;
+
+			if self.repeat_timer <= 0.0 {
+				actions.push(GamepadAction::Move(direction));
+
+				self.repeat_timer += self.repeat_interval;
+				self.repeat_interval = (self.repeat_interval * REPEAT_ACCELERATION).max(REPEAT_INTERVAL_MIN);
+			}
+		}
+
+		actions
+	}
+
+	/// Vibrates the most recently used gamepad, if it supports force feedback.
+	pub fn rumble(&mut self, strength: f32, duration: Duration) {
+		let (Some(gilrs), Some(id)) = (&mut self.gilrs, self.active_gamepad) else {
+			return
+		};
+
+		let supported = gilrs.connected_gamepad(id)
+			.map_or(false, |gamepad| gamepad.is_ff_supported());
+
+		if !supported {
+			return
+		}
+
+		let duration = ff::Ticks::from_ms(duration.as_millis() as u32);
+
+		let effect = ff::EffectBuilder::new()
+			.add_effect(ff::BaseEffect {
+				kind: ff::BaseEffectType::Strong { magnitude: (strength.clamp(0.0, 1.0) * u16::MAX as f32) as u16 },
+				scheduling: ff::Replay {
+					play_for: duration,
+					..Default::default()
+				},
+				..Default::default()
+			})
+			.repeat(ff::Repeat::For(duration))
+			.gamepads(&[id])
+			.finish(gilrs);
+
+		match effect.and_then(|effect| effect.play().map(|_| effect)) {
+			Ok(effect) => self.rumble_effect = Some(effect),
+			Err(error) => println!("[gamepad] Failed to play rumble: {error}"),
+		}
+	}
+}
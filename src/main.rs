@@ -6,87 +6,1588 @@ use toybox::prelude::*;
 fn main() -> anyhow::Result<()> {
 	std::env::set_var("RUST_BACKTRACE", "1");
 
-	toybox::run("my-nesweeper", App::new)
+	let args: Vec<String> = std::env::args().skip(1).collect();
+	if args.first().map(String::as_str) == Some("export-sounds") {
+		return export_sounds(&args[1..]);
+	}
+
+	let launch = LaunchOptions::parse(&args)?;
+	toybox::run("my-nesweeper", move |ctx| App::new(ctx, launch))
+}
+
+/// Lets multiplayer sessions be set up from the command line, e.g., to test several instances on one machine.
+/// Usage: `[--name <name>] [--coop | --versus] [--host [port] | --join <address>]`
+/// Sessions are races unless another mode is given. `--versus` on its own starts a hot-seat match.
+///
+/// Games can also be streamed to another instance with `--broadcast [port]`, and watched with
+/// `--spectate <address> [--delay <seconds>]`.
+#[derive(Default)]
+struct LaunchOptions {
+	name: Option<String>,
+	host: Option<u16>,
+	join: Option<String>,
+	mode: SessionMode,
+
+	broadcast: Option<u16>,
+	spectate: Option<String>,
+	delay: f32,
+}
+
+#[derive(Default, Copy, Clone, Debug, Eq, PartialEq)]
+enum SessionMode {
+	#[default]
+	Race,
+	Coop,
+	Versus,
+}
+
+impl LaunchOptions {
+	fn parse(args: &[String]) -> anyhow::Result<LaunchOptions> {
+		let mut options = LaunchOptions::default();
+
+		let mut args = args.iter().peekable();
+		while let Some(arg) = args.next() {
+			match arg.as_str() {
+				"--name" => {
+					options.name = Some(args.next().ok_or_else(|| anyhow::anyhow!("--name expects a name"))?.clone());
+				}
+
+				"--host" => {
+					let port = args.next_if(|arg| !arg.starts_with("--"))
+						.map(|port| port.parse())
+						.transpose()?;
+
+					options.host = Some(port.unwrap_or(net::DEFAULT_PORT));
+				}
+
+				"--join" => {
+					options.join = Some(args.next().ok_or_else(|| anyhow::anyhow!("--join expects an address"))?.clone());
+				}
+
+				"--coop" => options.mode = SessionMode::Coop,
+				"--versus" => options.mode = SessionMode::Versus,
+
+				"--broadcast" => {
+					let port = args.next_if(|arg| !arg.starts_with("--"))
+						.map(|port| port.parse())
+						.transpose()?;
+
+					options.broadcast = Some(port.unwrap_or(net::SPECTATE_PORT));
+				}
+
+				"--spectate" => {
+					options.spectate = Some(args.next().ok_or_else(|| anyhow::anyhow!("--spectate expects an address"))?.clone());
+				}
+
+				"--delay" => {
+					options.delay = args.next().ok_or_else(|| anyhow::anyhow!("--delay expects a number of seconds"))?.parse()?;
+				}
+
+				_ => anyhow::bail!("Unknown argument '{arg}'"),
+			}
+		}
+
+		Ok(options)
+	}
+}
+
+/// Renders every sound to a wav file without opening a window or audio device.
+/// Usage: `export-sounds [output dir] [--rate <sample rate>]`
+fn export_sounds(args: &[String]) -> anyhow::Result<()> {
+	let mut output_dir = std::path::PathBuf::from("sounds");
+	let mut sample_rate = 48000;
+
+	let mut args = args.iter();
+	while let Some(arg) = args.next() {
+		match arg.as_str() {
+			"--rate" => {
+				let rate = args.next().ok_or_else(|| anyhow::anyhow!("--rate expects a sample rate"))?;
+				sample_rate = rate.parse()?;
+			}
+
+			path => output_dir = path.into(),
+		}
+	}
+
+	std::fs::create_dir_all(&output_dir)?;
+
+	let patches = patch::PatchBank::load();
+
+	for sound in Sound::ALL {
+		let path = output_dir.join(format!("{}.wav", sound.name()));
+		sound::export_sound(patches.get(sound), sample_rate, &path)?;
+		println!("Wrote {}", path.display());
+	}
+
+	Ok(())
+}
+
+mod ext;
+use ext::*;
+
+mod sound;
+mod mixer;
+mod patch;
+mod music;
+mod sample;
+mod net;
+mod race;
+mod coop;
+mod versus;
+mod spectate;
+use sound::{SoundSystem, Sound, PlayParams};
+
+mod wav;
+mod quad_builder;
+mod board;
+mod view;
+mod map;
+mod settings;
+mod effects;
+mod resource;
+mod skin;
+mod palette;
+mod gamepad;
+mod touch;
+mod records;
+mod replay;
+mod daily;
+
+use board::*;
+use view::*;
+use settings::Settings;
+use effects::Effects;
+use skin::Skin;
+use gamepad::{Gamepad, GamepadAction};
+use touch::{TouchInput, TouchGesture};
+use records::Records;
+use replay::{Replay, ReplayPlayback};
+use daily::{DailyChallenge, DailyResult};
+use race::{RaceSession, RaceStart, RaceStatus, PlayerProgress};
+use coop::{CoopSession, CoopAction, CoopEvent};
+use versus::{VersusSession, VersusStart, VersusEvent, VersusOutcome};
+use spectate::{Broadcaster, Spectator, SpectateMessage};
+use palette::player_color32;
+
+use std::time::Duration;
+
+
+struct App {
+	board: Board,
+	board_view: BoardView,
+	effects: Effects,
+
+	sound: SoundSystem,
+	gamepad: Gamepad,
+	touch: TouchInput,
+	settings: Settings,
+	available_skins: Vec<String>,
+
+	board_size: Vec2i,
+	num_bombs: usize,
+
+	/// Pinch zoom and pan, relative to the board's resting position.
+	camera_zoom: f32,
+	camera_offset: Vec2,
+
+	/// Seeds board generation and anything else random, so replays look identical.
+	game_seed: u64,
+
+	status: GameStatus,
+	records: Records,
+	recording: Replay,
+	playback: Option<ReplayPlayback>,
+
+	/// Set while playing today's daily challenge. Only the first attempt each day is scored.
+	daily: Option<DailyChallenge>,
+	daily_scored: bool,
+
+	/// Set while in a LAN race. `race_starts_at` is when the countdown finishes, and input is allowed.
+	race: Option<RaceSession>,
+	race_starts_at: Option<f32>,
+
+	/// Set while clearing a shared board with other players. Only the host's board is authoritative - clients send
+	/// their actions to the host and show whatever it sends back.
+	coop: Option<CoopSession>,
+
+	/// Changes whenever a new game starts, so co-op clients can be sent the new board.
+	game_generation: u32,
+
+	/// Set while playing a Minesweeper Flags match, either hot-seat or against another instance.
+	versus: Option<VersusSession>,
+
+	/// Set while streaming this game to spectators, or while watching someone else's.
+	broadcaster: Option<Broadcaster>,
+	spectator: Option<Spectator>,
+
+	multiplayer_open: bool,
+	player_name: String,
+	host_port: u16,
+	join_address: String,
+	second_player_name: String,
+	broadcast_port: u16,
+	spectate_address: String,
+	spectate_delay: f32,
+
+	/// When the current game (or replay) began, and when the first and last cells were touched.
+	game_start_time: f32,
+	first_action_time: Option<f32>,
+	finish_time: Option<f32>,
+
+	/// 3BV progress when the game ended, since losing uncovers the whole board.
+	three_bv_at_finish: (usize, usize),
+	is_personal_best: bool,
+	overlay_open: bool,
+
+	debug_board: bool,
+	settings_open: bool,
+
+	/// Changed settings waiting to be saved, held back while a slider is being dragged.
+	settings_unsaved: bool,
+
+	/// Selected item in the pause menu, if it's open.
+	pause_menu: Option<usize>,
+
+	/// Both in seconds, from the engine's clock so animations keep pace with whatever drives frames.
+	frame_time: f32,
+	time: f32,
 }
 
-mod ext;
-use ext::*;
+impl App {
+	fn new(ctx: &mut toybox::Context, launch: LaunchOptions) -> anyhow::Result<App> {
+		let board_size = Vec2i::new(8, 8);
+		let num_bombs = 5;
+
+		let settings = Settings::load();
+
+		let game_seed = rand::random();
+		let board = Board::with_bombs(board_size, num_bombs, game_seed);
+		let recording = Replay::new(&board);
+
+		let skin = Skin::load(&mut ctx.gfx, &settings.skin)
+			.or_else(|error| {
+				println!("Failed to load skin '{}': {error}", settings.skin);
+				Skin::load(&mut ctx.gfx, Skin::DEFAULT)
+			})?;
+
+		let mut board_view = BoardView::new(ctx, board.size(), skin)?;
+		board_view.animations_enabled = settings.animations;
+		board_view.question_marks_enabled = settings.question_marks;
+		board_view.set_palette(settings.palette);
+
+		let sound = SoundSystem::start(&mut ctx.audio);
+		sound.set_mix(settings.audio);
+
+		let effects = Effects::new(ctx, game_seed)?;
+
+		let player_name = launch.name
+			.or_else(|| std::env::var("USER").ok())
+			.or_else(|| std::env::var("USERNAME").ok())
+			.unwrap_or_else(|| "Player".to_owned());
+
+		let second_player_name = "Player 2".to_owned();
+		let (mut race, mut coop, mut versus) = (None, None, None);
+
+		match (launch.mode, launch.host, &launch.join) {
+			(SessionMode::Race, Some(port), _) => race = Some(RaceSession::host(port, player_name.clone())?),
+			(SessionMode::Race, None, Some(address)) => race = Some(RaceSession::join(address, player_name.clone())?),
+			(SessionMode::Coop, Some(port), _) => coop = Some(CoopSession::host(port, player_name.clone())?),
+			(SessionMode::Coop, None, Some(address)) => coop = Some(CoopSession::join(address, player_name.clone())?),
+			(SessionMode::Versus, Some(port), _) => versus = Some(VersusSession::host(port, player_name.clone())?),
+			(SessionMode::Versus, None, Some(address)) => versus = Some(VersusSession::join(address, player_name.clone())?),
+			(SessionMode::Versus, None, None) => versus = Some(VersusSession::hot_seat([player_name.clone(), second_player_name.clone()])),
+			(_, None, None) => {}
+		}
+
+		let broadcaster = launch.broadcast.map(Broadcaster::bind).transpose()?;
+		let spectator = launch.spectate.as_deref()
+			.map(|address| Spectator::connect(address, launch.delay))
+			.transpose()?;
+
+		let mut app = App{
+			board,
+			board_view,
+			effects,
+
+			sound,
+			gamepad: Gamepad::new(),
+			touch: TouchInput::default(),
+			settings,
+			available_skins: Skin::available(),
+
+			board_size,
+			num_bombs,
+
+			camera_zoom: 1.0,
+			camera_offset: Vec2::zero(),
+
+			game_seed,
+
+			status: GameStatus::Playing,
+			records: Records::load(),
+			recording,
+			playback: None,
+
+			daily: None,
+			daily_scored: false,
+
+			multiplayer_open: race.is_some() || coop.is_some() || versus.is_some() || spectator.is_some(),
+			race,
+			race_starts_at: None,
+
+			coop,
+			game_generation: 0,
+
+			versus,
+
+			broadcaster,
+			spectator,
+
+			player_name,
+			host_port: net::DEFAULT_PORT,
+			join_address: format!("127.0.0.1:{}", net::DEFAULT_PORT),
+			second_player_name,
+			broadcast_port: net::SPECTATE_PORT,
+			spectate_address: format!("127.0.0.1:{}", net::SPECTATE_PORT),
+			spectate_delay: 0.0,
+
+			game_start_time: 0.0,
+			first_action_time: None,
+			finish_time: None,
+
+			three_bv_at_finish: (0, 0),
+			is_personal_best: false,
+			overlay_open: false,
+
+			debug_board: false,
+			settings_open: false,
+			settings_unsaved: false,
+
+			pause_menu: None,
+
+			frame_time: 0.0,
+			time: 0.0,
+		};
+
+		// Hot-seat matches can start straight away.
+		app.start_versus_match();
+
+		Ok(app)
+	}
+
+	fn show_board_debug(&mut self, ctx: &mut toybox::Context) {
+		if !self.debug_board {
+			return;
+		}
+
+		let mut do_reset = false;
+
+		egui::Window::new("Board")
+			.open(&mut self.debug_board)
+			.show(&ctx.egui, |ui| {
+				ui.add(egui::DragValue::new(&mut self.board_size.x).clamp_range(2..=30));
+				ui.add(egui::DragValue::new(&mut self.board_size.y).clamp_range(2..=30));
+				ui.add(egui::DragValue::new(&mut self.num_bombs).clamp_range(1..=100));
+
+				let mines_remaining = self.board.num_bombs() as isize - self.board.num_flags() as isize;
+				ui.label(format!("Mines remaining: {mines_remaining}"));
+
+				if ui.button("Reset").clicked() {
+					do_reset = true;
+				}
+
+				ui.horizontal(|ui| {
+					ui.label("Gaps");
+					ui.radio_value(&mut self.board_view.gap_mode, GapMode::Snap, "Snap");
+					ui.radio_value(&mut self.board_view.gap_mode, GapMode::Empty, "Empty");
+				});
+
+				ui.separator();
+
+				let stats = self.board_view.draw_stats;
+				ui.label(format!("Frame time: {:.2}ms", self.frame_time * 1000.0));
+				ui.label(format!("Board build time: {:.3}ms", stats.build_time.as_secs_f64() * 1000.0));
+				ui.label(format!("Cells rewritten: {}", stats.cells_rewritten));
+				ui.label(format!("Quads uploaded: {}", stats.quads_uploaded));
+
+				let sound_stats = self.sound.stats();
+				ui.label(format!("Sounds dropped (queue full): {}", sound_stats.dropped_queue_full));
+				ui.label(format!("Sounds dropped (disconnected): {}", sound_stats.dropped_disconnected));
+			});
+
+		if do_reset {
+			self.reset();
+		}
+	}
+
+	fn show_settings(&mut self, ctx: &mut toybox::Context) {
+		self.save_settings_when_idle(ctx);
+
+		if !self.settings_open {
+			return;
+		}
+
+		let previous_settings = self.settings.clone();
+
+		egui::Window::new("Settings")
+			.open(&mut self.settings_open)
+			.show(&ctx.egui, |ui| {
+				self.settings.ui(ui, &self.available_skins);
+			});
+
+		if self.settings == previous_settings {
+			return;
+		}
+
+		self.board_view.animations_enabled = self.settings.animations;
+		self.board_view.question_marks_enabled = self.settings.question_marks;
+		self.board_view.set_palette(self.settings.palette);
+
+		if self.settings.skin != self.board_view.skin.id {
+			match Skin::load(&mut ctx.gfx, &self.settings.skin) {
+				Ok(skin) => self.board_view.set_skin(&mut ctx.gfx, skin),
+				Err(error) => {
+					println!("Failed to load skin '{}': {error}", self.settings.skin);
+					self.settings.skin = self.board_view.skin.id.clone();
+				}
+			}
+		}
+
+		self.sound.set_mix(self.settings.audio);
+		self.settings_unsaved = true;
+	}
+
+	/// Dragging a slider changes settings every frame, so saving waits until the pointer is released.
+	fn save_settings_when_idle(&mut self, ctx: &mut toybox::Context) {
+		if !self.settings_unsaved || ctx.egui.input(|input| input.pointer.any_down()) {
+			return;
+		}
+
+		self.settings_unsaved = false;
+
+		if let Err(error) = self.settings.save() {
+			println!("Failed to save settings: {error}");
+		}
+	}
+
+	fn update_gamepad(&mut self) -> Option<(Vec2i, CellResponse)> {
+		let mut input = CursorInput::default();
+
+		for action in self.gamepad.update(self.frame_time) {
+			if let Some(selected) = self.pause_menu {
+				match action {
+					GamepadAction::Move(delta) => {
+						let num_items = PauseMenuItem::ALL.len() as i32;
+						self.pause_menu = Some((selected as i32 - delta.y).rem_euclid(num_items) as usize);
+					}
+
+					GamepadAction::Open => self.activate_pause_menu_item(PauseMenuItem::ALL[selected]),
+					GamepadAction::Back | GamepadAction::Menu => self.pause_menu = None,
+					_ => {}
+				}
+
+				continue
+			}
+
+			match action {
+				GamepadAction::Move(delta) => input.movement = input.movement + delta,
+				GamepadAction::Open => input.open = true,
+				GamepadAction::Flag => input.flag = true,
+				GamepadAction::Chord => input.chord = true,
+				GamepadAction::Menu => self.pause_menu = Some(0),
+				GamepadAction::Back => {}
+			}
+		}
+
+		self.board_view.apply_cursor_input(InputDevice::Gamepad, &self.board, input)
+	}
+
+	/// Applies two finger pan and pinch zoom. `view_scale` maps from NDC to view space at 1x zoom.
+	fn update_touch_camera(&mut self, ctx: &mut toybox::Context, view_scale: Vec2) {
+		let Some(multi_touch) = ctx.egui.multi_touch() else { return };
+
+		self.camera_zoom = (self.camera_zoom * multi_touch.zoom_delta).clamp(1.0, MAX_CAMERA_ZOOM);
+
+		let screen = ctx.egui.screen_rect();
+		let translation = multi_touch.translation_delta;
+		let translation_ndc = Vec2::new(2.0 * translation.x / screen.width(), -2.0 * translation.y / screen.height());
+
+		let bounds = self.board_view.bounds;
+		let offset = self.camera_offset - translation_ndc * view_scale / self.camera_zoom;
+		self.camera_offset = Vec2::new(
+			offset.x.clamp(bounds.min.x, bounds.max.x),
+			offset.y.clamp(bounds.min.y, bounds.max.y),
+		);
+	}
+
+	fn handle_touch_gesture(&mut self, ctx: &mut toybox::Context, gesture: TouchGesture, to_view: impl Fn(Vec2) -> Vec2) {
+		let (pos, long_press) = match gesture {
+			TouchGesture::Tap(pos) => (pos, false),
+			TouchGesture::LongPress(pos) => (pos, true),
+		};
+
+		// Ignore touches on ui, like the flag mode toggle.
+		if ctx.egui.layer_id_at(pos).is_some() {
+			return
+		}
+
+		let screen = ctx.egui.screen_rect();
+		let ndc = Vec2::new(
+			(pos.x - screen.min.x) / screen.width() * 2.0 - 1.0,
+			1.0 - (pos.y - screen.min.y) / screen.height() * 2.0,
+		);
+
+		let flag = long_press != self.touch.flag_mode;
+
+		if let Some((position, response)) = self.board_view.apply_touch(&self.board, to_view(ndc), flag) {
+			self.apply_player_action(response, position);
+		}
+	}
+
+	fn show_pause_menu(&mut self, ctx: &mut toybox::Context) {
+		let Some(selected) = self.pause_menu else { return };
+
+		let mut activated = None;
+
+		egui::Window::new("Paused")
+			.anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+			.collapsible(false)
+			.resizable(false)
+			.show(&ctx.egui, |ui| {
+				for (index, item) in PauseMenuItem::ALL.into_iter().enumerate() {
+					if ui.selectable_label(index == selected, item.label()).clicked() {
+						activated = Some(item);
+					}
+				}
+			});
+
+		if let Some(item) = activated {
+			self.activate_pause_menu_item(item);
+		}
+	}
+
+	fn activate_pause_menu_item(&mut self, item: PauseMenuItem) {
+		self.pause_menu = None;
+
+		match item {
+			PauseMenuItem::Resume => {}
+			PauseMenuItem::RetryBoard => self.retry(),
+			PauseMenuItem::NewGame => self.reset(),
+			PauseMenuItem::DailyChallenge => self.start_daily(),
+		}
+	}
+
+	fn show_multiplayer(&mut self, ctx: &mut toybox::Context) {
+		if !self.multiplayer_open {
+			return;
+		}
+
+		let mut start_race = false;
+		let mut leave = false;
+		let mut session_result = None;
+		let mut coop_result = None;
+		let mut versus_result = None;
+		let mut new_match = false;
+		let mut broadcast_result = None;
+		let mut spectate_result = None;
+		let mut stop_spectating = false;
+
+		egui::Window::new("Multiplayer")
+			.open(&mut self.multiplayer_open)
+			.show(&ctx.egui, |ui| {
+				ui.collapsing("Spectators", |ui| {
+					if let Some(broadcaster) = &self.broadcaster {
+						ui.label(format!("Broadcasting to {} spectator(s)", broadcaster.num_spectators()));
+
+						if ui.button("Stop broadcasting").clicked() {
+							stop_spectating = true;
+						}
+					} else if let Some(spectator) = &self.spectator {
+						ui.label(format!("Watching {} with a {:.1}s delay", spectator.peer(), spectator.delay));
+
+						if ui.button("Stop watching").clicked() {
+							stop_spectating = true;
+						}
+					} else {
+						ui.horizontal(|ui| {
+							ui.add(egui::DragValue::new(&mut self.broadcast_port).prefix("Port "));
+
+							if ui.button("Broadcast").clicked() {
+								broadcast_result = Some(Broadcaster::bind(self.broadcast_port));
+							}
+						});
+
+						ui.horizontal(|ui| {
+							ui.text_edit_singleline(&mut self.spectate_address);
+							ui.add(egui::DragValue::new(&mut self.spectate_delay).clamp_range(0.0..=60.0).suffix("s delay"));
+
+							if ui.button("Watch").clicked() {
+								spectate_result = Some(Spectator::connect(&self.spectate_address, self.spectate_delay));
+							}
+						});
+					}
+				});
+
+				ui.separator();
+
+				if let Some(race) = &self.race {
+					let role = if race.is_host() { "Hosting" } else { "Joined" };
+					ui.label(format!("{role} race as '{}' - {} player(s)", race.name, race.num_players()));
+
+					if race.is_host() && ui.button("Start race").clicked() {
+						start_race = true;
+					}
+
+					if ui.button("Leave").clicked() {
+						leave = true;
+					}
+
+					return;
+				}
+
+				if let Some(coop) = &self.coop {
+					let role = if coop.is_host() { "Hosting" } else { "Joined" };
+					ui.label(format!("{role} co-op as '{}' - {} player(s)", coop.name, coop.num_players()));
+
+					if !coop.is_connected() {
+						ui.label("Reconnecting...");
+					}
+
+					if ui.button("Leave").clicked() {
+						leave = true;
+					}
+
+					return;
+				}
+
+				if let Some(versus) = &self.versus {
+					let [first, second] = &versus.names;
+					let mode = if versus.is_hot_seat() { "Hot-seat versus" } else { "Versus" };
+					ui.label(format!("{mode} - {first} vs {second}"));
+
+					if !versus.has_opponent() {
+						ui.label("Waiting for an opponent...");
+					}
+
+					if versus.can_start_match() && ui.button("New match").clicked() {
+						new_match = true;
+					}
+
+					if ui.button("Leave").clicked() {
+						leave = true;
+					}
+
+					return;
+				}
+
+				ui.horizontal(|ui| {
+					ui.label("Name");
+					ui.text_edit_singleline(&mut self.player_name);
+				});
+
+				ui.separator();
+
+				ui.horizontal(|ui| {
+					ui.add(egui::DragValue::new(&mut self.host_port).prefix("Port "));
+
+					if ui.button("Host race").clicked() {
+						session_result = Some(RaceSession::host(self.host_port, self.player_name.clone()));
+					}
+
+					if ui.button("Host co-op").clicked() {
+						coop_result = Some(CoopSession::host(self.host_port, self.player_name.clone()));
+					}
+
+					if ui.button("Host versus").clicked() {
+						versus_result = Some(VersusSession::host(self.host_port, self.player_name.clone()));
+					}
+				});
+
+				ui.horizontal(|ui| {
+					ui.text_edit_singleline(&mut self.join_address);
+
+					if ui.button("Join race").clicked() {
+						session_result = Some(RaceSession::join(&self.join_address, self.player_name.clone()));
+					}
+
+					if ui.button("Join co-op").clicked() {
+						coop_result = Some(CoopSession::join(&self.join_address, self.player_name.clone()));
+					}
+
+					if ui.button("Join versus").clicked() {
+						versus_result = Some(VersusSession::join(&self.join_address, self.player_name.clone()));
+					}
+				});
+
+				ui.separator();
+
+				ui.horizontal(|ui| {
+					ui.label("Second player");
+					ui.text_edit_singleline(&mut self.second_player_name);
+
+					if ui.button("Hot-seat versus").clicked() {
+						let names = [self.player_name.clone(), self.second_player_name.clone()];
+						versus_result = Some(Ok(VersusSession::hot_seat(names)));
+					}
+				});
+			});
+
+		match session_result {
+			Some(Ok(race)) => self.race = Some(race),
+			Some(Err(error)) => println!("Failed to start race session: {error}"),
+			None => {}
+		}
+
+		match coop_result {
+			Some(Ok(coop)) => self.coop = Some(coop),
+			Some(Err(error)) => println!("Failed to start co-op session: {error}"),
+			None => {}
+		}
+
+		match versus_result {
+			Some(Ok(versus)) => {
+				self.versus = Some(versus);
+				self.start_versus_match();
+			}
+
+			Some(Err(error)) => println!("Failed to start versus session: {error}"),
+			None => {}
+		}
+
+		if new_match {
+			self.start_versus_match();
+		}
+
+		match broadcast_result {
+			Some(Ok(broadcaster)) => self.broadcaster = Some(broadcaster),
+			Some(Err(error)) => println!("Failed to start broadcasting: {error}"),
+			None => {}
+		}
+
+		match spectate_result {
+			Some(Ok(spectator)) => self.spectator = Some(spectator),
+			Some(Err(error)) => println!("Failed to start spectating: {error}"),
+			None => {}
+		}
+
+		if stop_spectating {
+			self.broadcaster = None;
+			self.spectator = None;
+		}
+
+		if start_race {
+			self.start_race();
+		}
+
+		if leave {
+			self.race = None;
+			self.race_starts_at = None;
+			self.coop = None;
+			self.board_view.remote_cursors.clear();
+			self.versus = None;
+			self.board_view.flag_owners.clear();
+		}
+	}
+
+	/// Host only. Races on the host's current board settings, with a fresh seed.
+	fn start_race(&mut self) {
+		let Some(race) = &mut self.race else { return };
+
+		race.start_race(RaceStart {
+			seed: rand::random(),
+			width: self.board_size.x,
+			height: self.board_size.y,
+			mines: self.num_bombs,
+			countdown: RACE_COUNTDOWN,
+		});
+	}
+
+	fn update_race(&mut self, ctx: &mut toybox::Context) {
+		let Some(race) = &mut self.race else { return };
+
+		race.update();
+
+		if !race.is_connected() {
+			println!("Lost connection to race host");
+			self.race = None;
+			self.race_starts_at = None;
+			return;
+		}
+
+		if let Some(start) = race.take_start() {
+			let board = start.make_board();
+			self.recording = Replay::new(&board);
+			self.start_game(board);
+			self.daily = None;
+
+			self.race_starts_at = Some(self.time + start.countdown);
+		}
+
+		let Some(progress) = self.race.as_ref().map(|race| self.local_race_progress(race)) else { return };
+		let Some(race) = &mut self.race else { return };
+		race.send_progress(progress);
+
+		egui::SidePanel::right("race")
+			.resizable(false)
+			.show(&ctx.egui, |ui| {
+				ui.heading("Race");
+
+				for opponent in race.opponents.iter() {
+					ui.separator();
+
+					let status = match opponent.status {
+						RaceStatus::Waiting => "waiting".to_owned(),
+						RaceStatus::Playing => format!("{:.0}s", opponent.time),
+						RaceStatus::Finished => format!("finished in {:.2}s", opponent.time),
+						RaceStatus::Failed => "boom".to_owned(),
+					};
+
+					ui.label(format!("{} - {status}", opponent.name));
+					ui.add(egui::ProgressBar::new(opponent.cleared).show_percentage());
+					race::draw_minimap(ui, opponent);
+				}
+			});
+
+		if let Some(starts_at) = self.race_starts_at.filter(|&starts_at| self.time < starts_at) {
+			egui::Area::new("race countdown")
+				.anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+				.interactable(false)
+				.show(&ctx.egui, |ui| {
+					ui.heading(format!("{}", (starts_at - self.time).ceil()));
+				});
+		}
+	}
+
+	fn local_race_progress(&self, race: &RaceSession) -> PlayerProgress {
+		let status = match (self.race_starts_at, self.status) {
+			(None, _) => RaceStatus::Waiting,
+			(Some(starts_at), _) if self.time < starts_at => RaceStatus::Waiting,
+			(_, GameStatus::Playing) => RaceStatus::Playing,
+			(_, GameStatus::Won) => RaceStatus::Finished,
+			(_, GameStatus::Lost) => RaceStatus::Failed,
+		};
+
+		let elapsed = self.race_starts_at
+			.map_or(0.0, |starts_at| (self.finish_time.unwrap_or(self.time) - starts_at).max(0.0));
+
+		// Only send whole seconds while playing, so progress isn't resent every frame.
+		let time = if status == RaceStatus::Playing { elapsed.floor() } else { elapsed };
+
+		PlayerProgress {
+			player_id: race.player_id,
+			name: race.name.clone(),
+			status,
+			cleared: self.board.cleared_fraction(),
+			flags: self.board.num_flags(),
+			time,
+			minimap: race::encode_minimap(&self.board),
+			minimap_width: self.board.size().x,
+		}
+	}
+
+	fn update_coop(&mut self) {
+		let Some(coop) = &mut self.coop else { return };
+
+		let events = coop.update();
+		coop.send_cursor(self.board_view.highlighted_cell());
+		self.board_view.remote_cursors = coop.remote_cursors();
+
+		for event in events {
+			match event {
+				CoopEvent::Action(action) => self.apply_coop_action(action),
+				CoopEvent::Snapshot(snapshot) => self.apply_snapshot(&snapshot),
+				CoopEvent::Diff { changes, bombs, detonated, status } => self.apply_coop_diff(changes, bombs, detonated, status),
+			}
+		}
+
+		if let Some(coop) = &mut self.coop {
+			coop.sync_board(&self.board, self.status, self.game_generation);
+		}
+	}
+
+	fn update_broadcast(&mut self) {
+		if let Some(broadcaster) = &mut self.broadcaster {
+			broadcaster.update(&self.board, self.status, self.game_generation);
+		}
+	}
+
+	/// Shows whatever the watched player did, once it's waited out the spectate delay.
+	fn update_spectating(&mut self) {
+		let Some(spectator) = &mut self.spectator else { return };
+
+		let messages = spectator.update(self.time);
+
+		if spectator.is_finished() {
+			println!("Stream ended");
+			self.spectator = None;
+		}
+
+		for message in messages {
+			match message {
+				SpectateMessage::Snapshot(snapshot) if snapshot.is_valid() => self.apply_snapshot(&snapshot),
+				SpectateMessage::Snapshot(_) => println!("Ignoring invalid snapshot from stream"),
+
+				SpectateMessage::Action { position: [x, y], response } => {
+					let position = Vec2i::new(x, y);
+
+					// Only apply actions that do the same thing on this board, so anything off the board or stale
+					// after a snapshot is dropped rather than corrupting it.
+					let local_response = CoopAction::from_response(&self.board, position, response)
+						.and_then(|action| action.response(&self.board));
+
+					if local_response != Some((position, response)) {
+						println!("Ignoring stream action that doesn't match the board");
+						continue;
+					}
+
+					self.first_action_time.get_or_insert(self.time);
+					self.handle_response(response, position);
+				}
+			}
+		}
+	}
+
+	/// Host only. Applies another player's action, if the cell is still how they saw it.
+	fn apply_coop_action(&mut self, action: CoopAction) {
+		if self.status != GameStatus::Playing || self.playback.is_some() {
+			return;
+		}
+
+		let Some((position, response)) = action.response(&self.board) else { return };
+
+		let first_action_time = *self.first_action_time.get_or_insert(self.time);
+		self.recording.record(self.time - first_action_time, position, response);
+
+		if let Some(broadcaster) = &mut self.broadcaster {
+			broadcaster.publish_action(position, response);
+		}
+
+		self.handle_response(response, position);
+	}
+
+	/// Replaces the board with another instance's, for co-op clients when they join or the host starts a new game,
+	/// and for spectators.
+	fn apply_snapshot(&mut self, snapshot: &BoardSnapshot) {
+		let board = snapshot.make_board();
+		self.recording = Replay::new(&board);
+		self.start_game(board);
+		self.daily = None;
+
+		self.status = snapshot.status;
+
+		if self.status != GameStatus::Playing {
+			self.finish_time = Some(self.time);
+		}
+	}
+
+	/// Client only. Shows changes made to the host's board, by anyone including this player.
+	fn apply_coop_diff(&mut self, changes: Vec<(Vec2i, CellState)>, bombs: Option<Vec<Vec2i>>, detonated: Option<Vec2i>, status: GameStatus) {
+		// Changes come from the host, so anything off the board is ignored rather than trusted.
+		let detonated = detonated.filter(|&position| self.board.states.in_bounds(position));
+
+		if let Some(bombs) = bombs {
+			// A first click moved a mine.
+			self.board.types = Board::from_bombs(self.board.size(), &bombs, self.board.seed()).types;
+		}
+
+		if !changes.is_empty() {
+			self.first_action_time.get_or_insert(self.time);
+		}
+
+		// Finish before applying the last changes, so 3BV is counted from before the board was uncovered.
+		if status != GameStatus::Playing && self.status == GameStatus::Playing {
+			self.board.detonated = detonated;
+			self.finish_game(status);
+		}
+
+		let mut opened = Vec::new();
+		let mut feedback = None;
+
+		for (position, state) in changes {
+			let Some(current_state) = self.board.states.get_mut(position) else { continue };
+			*current_state = state;
+
+			let sound = match state {
+				CellState::Opened => {
+					opened.push(position);
+					continue
+				}
+
+				CellState::Flagged => {
+					self.board_view.animate_flag(self.time, position);
+					Sound::Thup
+				}
+
+				CellState::Questioned => Sound::Hmm,
+				CellState::Unopened => Sound::Unthup,
+			};
+
+			feedback.get_or_insert((sound, position));
+		}
+
+		self.board.detonated = detonated;
+
+		match (status, detonated) {
+			(GameStatus::Lost, Some(origin)) if !opened.is_empty() => {
+				self.board_view.animate_ripple(self.time, origin, opened);
+
+				let cell_bounds = self.board_view.cell_bounds(origin);
+				self.effects.explode(cell_bounds.center(), cell_bounds.size().y);
+				self.gamepad.rumble(0.8, Duration::from_millis(400));
+				self.sound.play(Sound::Bong, self.cell_sound_params(origin));
+			}
+
+			(GameStatus::Won, _) if !opened.is_empty() => {
+				let origin = feedback.map_or(opened[0], |(_, position)| position);
+				self.board_view.animate_ripple(self.time, origin, opened);
+				self.effects.confetti(self.board_view.bounds);
+				self.sound.play(Sound::Tada, self.cell_sound_params(origin));
+			}
+
+			_ => {
+				if let Some(&first_opened) = opened.first() {
+					self.play_reveal_sound(first_opened);
+				}
+
+				if let Some((sound, position)) = feedback {
+					self.sound.play(sound, self.cell_sound_params(position));
+				}
+
+				self.board_view.animate_reveal(self.time, opened.into_iter().map(|position| (position, 0)));
+			}
+		}
+	}
+
+	/// Host and hot-seat only. Deals a new board for both players.
+	fn start_versus_match(&mut self) {
+		let Some(versus) = &mut self.versus else { return };
+
+		versus.start_match(self.board_size, VersusSession::mine_count(self.board_size, self.num_bombs));
+	}
+
+	fn update_versus(&mut self, ctx: &mut toybox::Context) {
+		let Some(versus) = &mut self.versus else { return };
+
+		let events = versus.update();
+
+		if !versus.is_connected() {
+			println!("Lost connection to versus host");
+			self.versus = None;
+			self.board_view.flag_owners.clear();
+			return;
+		}
+
+		for event in events {
+			match event {
+				VersusEvent::Joined => self.start_versus_match(),
+				VersusEvent::Start(start) => self.begin_versus_match(start),
+
+				VersusEvent::Open(position) => {
+					if self.versus.as_ref().is_some_and(|versus| !versus.is_local_turn()) {
+						self.apply_versus_open(position);
+					}
+				}
+			}
+		}
+
+		let Some(versus) = &self.versus else { return };
+		self.board_view.flag_owners.clone_from(&versus.claims);
 
-mod sound;
-use sound::{SoundSystem, Sound};
+		egui::SidePanel::right("versus")
+			.resizable(false)
+			.show(&ctx.egui, |ui| {
+				ui.heading("Versus");
 
-mod quad_builder;
-mod board;
-mod view;
-mod map;
+				if !versus.has_opponent() {
+					ui.label("Waiting for an opponent...");
+					return;
+				}
 
-use board::*;
-use view::*;
+				for (player, name) in versus.names.iter().enumerate() {
+					ui.separator();
 
+					let to_play = if player == versus.turn && self.status == GameStatus::Playing { " - to play" } else { "" };
+					ui.colored_label(player_color32(player), format!("{name}{to_play}"));
+					ui.label(format!("Mines: {}", versus.scores[player]));
+				}
 
-struct App {
-	board: Board,
-	board_view: BoardView,
+				ui.separator();
 
-	sound: SoundSystem,
+				let mines_left = self.board.num_bombs().saturating_sub(versus.claims.len());
+				ui.label(format!("Mines left: {mines_left}"));
+			});
+	}
 
-	board_size: Vec2i,
-	num_bombs: usize,
+	fn begin_versus_match(&mut self, start: VersusStart) {
+		let board = start.make_board();
+		self.recording = Replay::new(&board);
+		self.start_game(board);
+		self.daily = None;
 
-	debug_board: bool,
-}
+		if let Some(versus) = &mut self.versus {
+			versus.begin(&start);
+		}
+	}
 
-impl App {
-	fn new(ctx: &mut toybox::Context) -> anyhow::Result<App> {
-		let board_size = Vec2i::new(8, 8);
-		let num_bombs = 5;
+	/// Opens a cell for whoever's turn it is. Mines are claimed rather than set off, and keep the turn.
+	fn apply_versus_open(&mut self, cell_position: Vec2i) {
+		if self.status != GameStatus::Playing {
+			return;
+		}
 
-		let board = Board::with_bombs(board_size, num_bombs);
-		let board_view = BoardView::new(ctx, board.size())?;
+		let Some(response) = open_response(&self.board, cell_position) else { return };
 
-		Ok(App{
-			board,
-			board_view,
+		self.first_action_time.get_or_insert(self.time);
 
-			sound: SoundSystem::start(&mut ctx.audio)?,
+		if response == CellResponse::BombHit {
+			if let Some(versus) = &mut self.versus {
+				versus.claim(cell_position);
+			}
 
-			board_size,
-			num_bombs,
+			self.board.states.set(cell_position, CellState::Flagged);
+			self.board_view.animate_flag(self.time, cell_position);
+			self.sound.play(Sound::Thup, self.cell_sound_params(cell_position));
+		} else {
+			if let Some(versus) = &mut self.versus {
+				versus.pass_turn();
+			}
 
-			debug_board: false,
-		})
+			self.handle_response(response, cell_position);
+		}
+
+		let Some(versus) = &self.versus else { return };
+		let Some(outcome) = versus.outcome(self.board.num_bombs()) else { return };
+
+		// Hot-seat games are always a win for somebody at the table.
+		let won = match (outcome, versus.local_player()) {
+			(VersusOutcome::Winner(winner), Some(local_player)) => winner == local_player,
+			(VersusOutcome::Winner(_), None) => true,
+			(VersusOutcome::Draw, _) => false,
+		};
+
+		self.finish_game(if won { GameStatus::Won } else { GameStatus::Lost });
+
+		let uncovered = self.board.uncover_all();
+		self.board_view.animate_ripple(self.time, cell_position, uncovered);
+
+		let params = self.cell_sound_params(cell_position);
+
+		match outcome {
+			VersusOutcome::Draw => self.sound.play(Sound::Hmm, params),
+
+			_ if won => {
+				self.effects.confetti(self.board_view.bounds);
+				self.sound.play(Sound::Tada, params);
+			}
+
+			_ => self.sound.play(Sound::Bong, params),
+		}
 	}
 
-	fn show_board_debug(&mut self, ctx: &mut toybox::Context) {
-		if !self.debug_board {
+	fn is_coop_client(&self) -> bool {
+		self.coop.as_ref().is_some_and(|coop| !coop.is_host())
+	}
+
+	/// Co-op clients and spectators show someone else's board, and can't start games of their own.
+	fn follows_remote_board(&self) -> bool {
+		self.is_coop_client() || self.spectator.is_some()
+	}
+
+	/// Versus boards are dealt for each match, so can't be retried or swapped for a daily challenge either.
+	fn can_start_own_game(&self) -> bool {
+		!self.follows_remote_board() && self.versus.is_none()
+	}
+
+	fn show_game_over(&mut self, ctx: &mut toybox::Context) {
+		if !self.overlay_open || self.status == GameStatus::Playing {
 			return;
 		}
 
-		let mut do_reset = false;
+		let title = match (&self.versus, self.status) {
+			(Some(versus), _) => match versus.outcome(self.board.num_bombs()) {
+				Some(VersusOutcome::Winner(winner)) => format!("{} wins!", versus.names[winner]),
+				_ => "It's a draw".to_owned(),
+			},
 
-		egui::Window::new("Board")
-			.open(&mut self.debug_board)
+			(None, GameStatus::Won) => "You win!".to_owned(),
+			(None, _) => "Boom!".to_owned(),
+		};
+
+		let mut action = None;
+		let follows_remote_board = self.follows_remote_board();
+
+		egui::Window::new(title)
+			.anchor(egui::Align2::CENTER_CENTER, [0.0, 0.0])
+			.collapsible(false)
+			.resizable(false)
 			.show(&ctx.egui, |ui| {
-				ui.add(egui::DragValue::new(&mut self.board_size.x).clamp_range(2..=30));
-				ui.add(egui::DragValue::new(&mut self.board_size.y).clamp_range(2..=30));
-				ui.add(egui::DragValue::new(&mut self.num_bombs).clamp_range(1..=100));
+				if let Some(versus) = &self.versus {
+					for (player, name) in versus.names.iter().enumerate() {
+						ui.colored_label(player_color32(player), format!("{name}: {} mines", versus.scores[player]));
+					}
 
-				if ui.button("Reset").clicked() {
-					do_reset = true;
+					ui.separator();
+
+					ui.horizontal(|ui| {
+						if versus.can_start_match() && ui.button("Rematch").clicked() {
+							action = Some(GameOverAction::Rematch);
+						}
+
+						if ui.button("Dismiss").clicked() {
+							action = Some(GameOverAction::Dismiss);
+						}
+					});
+
+					return;
+				}
+
+				if let Some(daily) = &self.daily {
+					ui.label(format!("Daily challenge {}", daily.date));
+				}
+
+				ui.label(format!("Time: {:.2}s", self.game_duration()));
+
+				match self.status {
+					GameStatus::Won if self.is_personal_best => {
+						ui.label("New personal best!");
+					}
+
+					GameStatus::Won => {
+						if let Some(best) = self.records.best_time(self.board.size(), self.board.num_bombs()) {
+							ui.label(format!("Personal best: {best:.2}s"));
+						}
+					}
+
+					_ => {
+						let (solved, total) = self.three_bv_at_finish;
+						ui.label(format!("3BV: {solved}/{total}"));
+					}
+				}
+
+				if let Some(daily) = &self.daily {
+					match self.records.daily_result(&daily.date) {
+						Some(result) if result.finished => {
+							ui.label(format!("3BV/s: {:.2}", result.three_bv_per_second()));
+
+							if ui.button("Copy result").clicked() {
+								let text = result.share_text();
+								ctx.egui.output_mut(|output| output.copied_text = text);
+							}
+						}
+
+						_ => {
+							ui.label("Practice - today's scored attempt has already been used");
+						}
+					}
 				}
+
+				ui.separator();
+
+				ui.horizontal(|ui| {
+					if follows_remote_board {
+						ui.label("Waiting for the next game");
+					} else {
+						if ui.button("Retry same board").clicked() {
+							action = Some(GameOverAction::Retry);
+						}
+
+						if ui.button("New board").clicked() {
+							action = Some(GameOverAction::NewBoard);
+						}
+
+						if ui.button("Replay").clicked() {
+							action = Some(GameOverAction::Replay);
+						}
+					}
+
+					if ui.button("Dismiss").clicked() {
+						action = Some(GameOverAction::Dismiss);
+					}
+				});
 			});
 
-		if do_reset {
-			self.reset();
+		match action {
+			Some(GameOverAction::Retry) => self.retry(),
+			Some(GameOverAction::NewBoard) => self.reset(),
+			Some(GameOverAction::Replay) => self.start_replay(),
+			Some(GameOverAction::Rematch) => self.start_versus_match(),
+			Some(GameOverAction::Dismiss) => self.overlay_open = false,
+			None => {}
 		}
 	}
 
 	fn reset(&mut self) {
-		self.board = Board::with_bombs(self.board_size, self.num_bombs);
-		self.board_view.reset(self.board_size);
+		if self.follows_remote_board() {
+			return;
+		}
+
+		if self.versus.is_some() {
+			self.start_versus_match();
+			return;
+		}
+
+		self.game_seed = rand::random();
+		let board = Board::with_bombs(self.board_size, self.num_bombs, self.game_seed);
+
+		self.recording = Replay::new(&board);
+		self.start_game(board);
+		self.daily = None;
+	}
+
+	fn start_daily(&mut self) {
+		if !self.can_start_own_game() {
+			return;
+		}
+
+		let daily = DailyChallenge::today();
+		let board = daily.make_board();
+
+		self.recording = Replay::new(&board);
+		self.start_game(board);
+
+		self.daily_scored = self.records.daily_result(&daily.date).is_none();
+		self.daily = Some(daily);
+	}
+
+	/// Restarts on the same layout. A mine moved by the first click either stays put, or goes back to
+	/// its original position with a new seed so the next first click moves it somewhere else.
+	fn retry(&mut self) {
+		if !self.can_start_own_game() {
+			return;
+		}
+
+		let board = if self.settings.retry_keeps_relocation {
+			let mut board = std::mem::replace(&mut self.board, Board::empty(self.board.size()));
+			board.reset_states();
+			board
+		} else {
+			Board::from_bombs(self.recording.board_size, &self.recording.bombs, rand::random())
+		};
+
+		self.recording = Replay::new(&board);
+		self.start_game(board);
+	}
+
+	/// Plays back the last game from the start. Board input is ignored until it's done.
+	fn start_replay(&mut self) {
+		if !self.can_start_own_game() {
+			return;
+		}
+
+		let board = self.recording.make_board();
+		self.start_game(board);
+		self.playback = Some(ReplayPlayback::new(self.recording.clone()));
+	}
+
+	fn start_game(&mut self, board: Board) {
+		self.board_view.reset(board.size());
+		self.game_seed = board.seed();
+		self.board = board;
+
+		self.camera_zoom = 1.0;
+		self.camera_offset = Vec2::zero();
+
+		self.effects.reset(self.game_seed);
+
+		self.status = GameStatus::Playing;
+		self.playback = None;
+		self.game_start_time = self.time;
+		self.first_action_time = None;
+		self.finish_time = None;
+		self.is_personal_best = false;
+		self.overlay_open = false;
+		self.daily_scored = false;
+		self.race_starts_at = None;
+		self.game_generation = self.game_generation.wrapping_add(1);
+	}
+
+	fn update_playback(&mut self) {
+		let Some(playback) = &mut self.playback else { return };
+
+		let game_time = self.time - self.game_start_time;
+		let mut due_actions = Vec::new();
+		while let Some(action) = playback.next_due(game_time) {
+			due_actions.push(action);
+		}
+
+		let is_finished = playback.is_finished();
+
+		for action in due_actions {
+			self.first_action_time.get_or_insert(self.time);
+			self.handle_response(action.response, action.position);
+		}
+
+		if is_finished {
+			self.playback = None;
+		}
+	}
+
+	fn game_duration(&self) -> f32 {
+		let Some(first_action_time) = self.first_action_time else { return 0.0 };
+		self.finish_time.unwrap_or(self.time) - first_action_time
+	}
+
+	fn accepts_board_input(&self) -> bool {
+		self.pause_menu.is_none()
+			&& self.playback.is_none()
+			&& !self.overlay_open
+			&& self.race_starts_at.map_or(true, |starts_at| self.time >= starts_at)
+			&& self.spectator.is_none()
+	}
+
+	/// Applies an action the player made directly, recording it for replays.
+	fn apply_player_action(&mut self, response: CellResponse, cell_position: Vec2i) {
+		if !self.accepts_board_input() {
+			return;
+		}
+
+		// Versus players can only open cells, and only on their own turn.
+		if let Some(versus) = &self.versus {
+			let is_open = matches!(response, CellResponse::BombHit | CellResponse::OpenSpaceUncovered | CellResponse::UnsafeSpaceUncovered);
+			if !is_open || !versus.is_local_turn() || !versus.has_opponent() {
+				return;
+			}
+
+			versus.send_open(cell_position);
+			self.apply_versus_open(cell_position);
+			return;
+		}
+
+		// Co-op clients only ask the host for changes, and wait to hear back.
+		if self.is_coop_client() {
+			let action = CoopAction::from_response(&self.board, cell_position, response);
+			if let Some((coop, action)) = self.coop.as_ref().zip(action) {
+				coop.send_action(action);
+			}
+
+			return;
+		}
+
+		// A finished board stays as it ended, even once the overlay is dismissed. Otherwise flags could be edited
+		// after a loss, or toggled after a win to finish the game again.
+		if self.status != GameStatus::Playing {
+			return;
+		}
+
+		if self.first_action_time.is_none() && self.daily_scored {
+			// The attempt counts as soon as it starts.
+			if let Some(daily) = &self.daily {
+				self.records.submit_daily(DailyResult::started(&daily.date));
+				self.save_records();
+			}
+		}
+
+		let first_action_time = *self.first_action_time.get_or_insert(self.time);
+		self.recording.record(self.time - first_action_time, cell_position, response);
+
+		if let Some(broadcaster) = &mut self.broadcaster {
+			broadcaster.publish_action(cell_position, response);
+		}
+
+		self.handle_response(response, cell_position);
+	}
+
+	fn finish_game(&mut self, status: GameStatus) {
+		self.status = status;
+		self.finish_time = Some(self.time);
+		self.three_bv_at_finish = (self.board.three_bv_solved(), self.board.three_bv());
+		self.overlay_open = true;
+
+		// Replays, spectated games and multiplayer games other than races don't count towards records.
+		if self.playback.is_some() || self.spectator.is_some() || self.coop.is_some() || self.versus.is_some() {
+			return;
+		}
+
+		if status == GameStatus::Won {
+			self.is_personal_best = self.records.submit_time(self.board.size(), self.board.num_bombs(), self.game_duration());
+
+			if self.is_personal_best {
+				self.save_records();
+			}
+		}
+
+		if let Some(daily) = self.daily.as_ref().filter(|_| self.daily_scored) {
+			let (three_bv_solved, three_bv) = self.three_bv_at_finish;
+
+			self.records.submit_daily(DailyResult {
+				date: daily.date.clone(),
+				finished: true,
+				won: status == GameStatus::Won,
+				seconds: self.game_duration(),
+				three_bv,
+				three_bv_solved,
+				grid: daily::emoji_grid(&self.board),
+			});
+
+			self.daily_scored = false;
+			self.save_records();
+		}
+	}
+
+	fn save_records(&self) {
+		if let Err(error) = self.records.save() {
+			println!("Failed to save records: {error}");
+		}
+	}
+
+	/// Pans sounds to follow the column of the cell that caused them.
+	fn cell_sound_params(&self, cell_position: Vec2i) -> PlayParams {
+		let width = self.board.size().x.max(1) as f32;
+		let pan = (cell_position.x as f32 + 0.5) / width * 2.0 - 1.0;
+
+		PlayParams {
+			pan: pan * MAX_SOUND_PAN,
+			..PlayParams::default()
+		}
+	}
+
+	/// Maps numbered cells to a rising scale of whole tones.
+	fn cell_pitch(&self, cell_position: Vec2i) -> f32 {
+		match self.board.types.get(cell_position) {
+			Some(&CellType::BombAdjacent(count)) if self.settings.pitch_variation => sound::semitones(2.0 * (count as f32 - 1.0)),
+			_ => 1.0,
+		}
+	}
+
+	fn play_reveal_sound(&self, cell_position: Vec2i) {
+		self.sound.play(Sound::Plik, PlayParams {
+			pitch: self.cell_pitch(cell_position),
+			..self.cell_sound_params(cell_position)
+		});
+	}
+
+	/// Plays a rising run of pliks, in time with a flood fill spreading out from `origin`.
+	fn play_flood_sounds(&self, origin: Vec2i, opened: &[(Vec2i, usize)]) {
+		if !self.settings.pitch_variation {
+			return
+		}
+
+		let stagger = if self.board_view.animations_enabled { view::REVEAL_STAGGER } else { 0.0 };
+
+		// Without a stagger every note starts at once, so leave a voice free for the reveal sound.
+		let max_notes = match stagger > 0.0 {
+			true => MAX_FLOOD_NOTES,
+			false => MAX_FLOOD_NOTES.min(self.settings.audio.max_voices.saturating_sub(1)),
+		};
+
+		let max_distance = opened.iter()
+			.map(|&(_, distance)| distance)
+			.max().unwrap_or(0)
+			.min(max_notes);
+
+		for distance in 1..=max_distance {
+			self.sound.play(Sound::Plik, PlayParams {
+				pitch: sound::semitones(distance as f32),
+				gain: 0.5,
+				delay: distance as f32 * stagger,
+				..self.cell_sound_params(origin)
+			});
+		}
 	}
 
 	fn handle_response(&mut self, response: CellResponse, cell_position: Vec2i) {
+		let params = self.cell_sound_params(cell_position);
+
 		match response {
 			CellResponse::BombHit => {
 				let is_first_opened_cell = self.board.states.iter()
@@ -96,43 +1597,88 @@ impl App {
 
 				// First click is always safe
 				if is_first_opened_cell {
-					self.sound.play(Sound::Plik);
 					self.board.move_bomb(cell_position);
+					self.play_reveal_sound(cell_position);
+					self.board_view.animate_reveal(self.time, [(cell_position, 0)]);
+
+					// If the newly empty cell has no adjacent bombs, flood fill as normal
+					if self.board.types.get(cell_position) == Some(&CellType::Empty) {
+						let opened = self.board.flood_uncover_empty(cell_position);
+						self.play_flood_sounds(cell_position, &opened);
+						self.board_view.animate_reveal(self.time, opened);
+					}
+
 					return;
 				}
 
-				self.board.uncover_all();
-				self.sound.play(Sound::Bong);
-				println!("LOSE!")
+				self.board.detonated = Some(cell_position);
+				self.finish_game(GameStatus::Lost);
+
+				let uncovered = self.board.uncover_all();
+				self.board_view.animate_ripple(self.time, cell_position, uncovered);
+
+				let cell_bounds = self.board_view.cell_bounds(cell_position);
+				self.effects.explode(cell_bounds.center(), cell_bounds.size().y);
+				self.gamepad.rumble(0.8, Duration::from_millis(400));
+
+				self.sound.play(Sound::Bong, params);
 			}
 
 			CellResponse::FlagPlaced => {
 				self.board.states.set(cell_position, CellState::Flagged);
+				self.board_view.animate_flag(self.time, cell_position);
 
 				if self.board.are_all_bombs_flagged() {
-					self.board.uncover_all();
-					self.sound.play(Sound::Tada);
-					println!("WIN!");
+					self.finish_game(GameStatus::Won);
+
+					let uncovered = self.board.uncover_all();
+					self.board_view.animate_ripple(self.time, cell_position, uncovered);
+					self.effects.confetti(self.board_view.bounds);
+					self.sound.play(Sound::Tada, params);
 				} else {
-					self.sound.play(Sound::Thup);
+					self.sound.play(Sound::Thup, params);
 				}
 			}
 
 			CellResponse::FlagRemoved => {
 				self.board.states.set(cell_position, CellState::Unopened);
-				self.sound.play(Sound::Unthup);
+				self.sound.play(Sound::Unthup, params);
+			}
+
+			CellResponse::QuestionPlaced => {
+				self.board.states.set(cell_position, CellState::Questioned);
+				self.sound.play(Sound::Hmm, params);
+			}
+
+			CellResponse::QuestionRemoved => {
+				self.board.states.set(cell_position, CellState::Unopened);
+				self.sound.play(Sound::Unthup, params);
 			}
 
 			CellResponse::OpenSpaceUncovered => {
 				self.board.states.set(cell_position, CellState::Opened);
+				self.board_view.animate_reveal(self.time, [(cell_position, 0)]);
 
-				self.sound.play(Sound::Plik);
-				self.board.flood_uncover_empty(cell_position);
+				self.play_reveal_sound(cell_position);
+				let opened = self.board.flood_uncover_empty(cell_position);
+				self.play_flood_sounds(cell_position, &opened);
+				self.board_view.animate_reveal(self.time, opened);
 			}
 
 			CellResponse::UnsafeSpaceUncovered => {
 				self.board.states.set(cell_position, CellState::Opened);
-				self.sound.play(Sound::Plik);
+				self.board_view.animate_reveal(self.time, [(cell_position, 0)]);
+				self.play_reveal_sound(cell_position);
+			}
+
+			CellResponse::Chord => {
+				for neighbour in self.board.chord_targets(cell_position) {
+					// Responses are recalculated for each neighbour since earlier ones may have flood filled
+					// or ended the game.
+					if let Some(response) = open_response(&self.board, neighbour) {
+						self.handle_response(response, neighbour);
+					}
+				}
 			}
 		}
 	}
@@ -140,12 +1686,32 @@ impl App {
 
 impl toybox::App for App {
 	fn present(&mut self, ctx: &mut toybox::Context) {
+		let (time, frame_time) = ctx.egui.input(|input| (input.time, input.stable_dt));
+		self.time = time as f32;
+		self.frame_time = frame_time;
+
 		self.show_board_debug(ctx);
+		self.show_settings(ctx);
+		self.sound.update();
 
 		if ctx.input.button_just_down(input::Key::F5) {
 			self.reset();
 		}
 
+		let gamepad_response = self.update_gamepad();
+		self.show_pause_menu(ctx);
+		self.show_game_over(ctx);
+		self.show_multiplayer(ctx);
+		self.update_race(ctx);
+		self.update_coop();
+		self.update_versus(ctx);
+		self.update_spectating();
+		self.update_broadcast();
+		self.update_playback();
+
+		let touch_gestures = self.touch.update(&ctx.egui, &self.settings.touch);
+		self.touch.draw_ui(&ctx.egui, &self.settings.touch);
+
 		let _ = ctx.gfx.frame_encoder.command_group("main");
 
 		ctx.gfx.frame_encoder.backbuffer_color([0.1; 3]);
@@ -162,28 +1728,54 @@ impl toybox::App for App {
 			(board_aspect / aspect).max(1.0)
 		};
 
+		// TODO(pat.m): how to not need to do this
+		let view_scale = match aspect {
+			aspect if aspect >= 1.0 => Vec2::new(aspect, 1.0) * safe_zone,
+			aspect => Vec2::new(1.0, 1.0/aspect) * safe_zone,
+		};
+
+		self.update_touch_camera(ctx, view_scale);
+
+		let (camera_zoom, camera_offset) = (self.camera_zoom, self.camera_offset);
+		let ndc_to_view = move |ndc: Vec2| ndc * view_scale / camera_zoom + camera_offset;
+
+		self.effects.update(self.frame_time);
+
+		self.sound.set_music_state(music::MusicState {
+			intensity: self.board.cleared_fraction(),
+			playing: self.status == GameStatus::Playing,
+		});
+
 		let global_uniforms = GlobalUniforms {
 			projection: {
+				let shake = self.effects.shake_offset();
 				Mat4::ortho_aspect(safe_zone, aspect, -1.0, 1.0)
+					* Mat4::scale(Vec3::new(camera_zoom, camera_zoom, 1.0))
+					* Mat4::translate((shake - camera_offset).extend(0.0))
 			}
 		};
 
+		let mouse_pos_view = ctx.input.pointer_position().map(ndc_to_view);
 
-		// TODO(pat.m): how to not need to do this
-		let mouse_pos_view = ctx.input.pointer_position()
-			.map(|ndc| match ctx.gfx.backbuffer_aspect() {
-				aspect if aspect >= 1.0 => ndc * Vec2::new(aspect, 1.0) * safe_zone,
-				aspect => ndc * Vec2::new(1.0, 1.0/aspect) * safe_zone,
-			});
+		if self.accepts_board_input() {
+			for gesture in touch_gestures {
+				self.handle_touch_gesture(ctx, gesture, ndc_to_view);
+			}
 
-		if let Some((position, response)) = self.board_view.update(ctx, &self.board, mouse_pos_view) {
-			self.handle_response(response, position);
+			let response = gamepad_response
+				.or_else(|| self.board_view.update(ctx, &self.board, mouse_pos_view));
+
+			if let Some((position, response)) = response {
+				self.apply_player_action(response, position);
+			}
 		}
 
 
 		ctx.gfx.frame_encoder.bind_global_ubo(0, &[global_uniforms]);
 
-		self.board_view.draw(&mut ctx.gfx, &self.board);
+		self.board_view.draw(&mut ctx.gfx, &self.board, self.time);
+
+		self.effects.draw(&mut ctx.gfx, &self.board_view.atlas());
 	}
 
 	fn customise_debug_menu(&mut self, ui: &mut egui::Ui) {
@@ -191,12 +1783,78 @@ impl toybox::App for App {
 			self.debug_board = true;
 		});
 
+		if ui.button("Settings").clicked() {
+			self.settings_open = true;
+		}
+
 		if ui.button("Reset").clicked() {
 			self.reset();
 		}
 
+		if ui.button("Retry").clicked() {
+			self.retry();
+		}
+
+		if ui.button("Daily challenge").clicked() {
+			self.start_daily();
+		}
+
+		if ui.button("Multiplayer").clicked() {
+			self.multiplayer_open = true;
+		}
+
 		if ui.button("Plik").clicked() {
-			self.sound.play(Sound::Plik);
+			self.sound.play(Sound::Plik, PlayParams::default());
+		}
+	}
+}
+
+
+
+const MAX_CAMERA_ZOOM: f32 = 4.0;
+
+/// Seconds between the host starting a race and players being able to open cells.
+const RACE_COUNTDOWN: f32 = 3.0;
+
+/// How far sounds are panned for cells at the edge of the board. Hard panning is a bit much on headphones.
+const MAX_SOUND_PAN: f32 = 0.8;
+
+/// Flood fill distance at which pliks stop rising, in semitones.
+const MAX_FLOOD_NOTES: usize = 12;
+
+
+#[derive(Copy, Clone, Debug)]
+enum GameOverAction {
+	Retry,
+	NewBoard,
+	Replay,
+	Rematch,
+	Dismiss,
+}
+
+
+#[derive(Copy, Clone, Debug)]
+enum PauseMenuItem {
+	Resume,
+	RetryBoard,
+	NewGame,
+	DailyChallenge,
+}
+
+impl PauseMenuItem {
+	const ALL: [PauseMenuItem; 4] = [
+		PauseMenuItem::Resume,
+		PauseMenuItem::RetryBoard,
+		PauseMenuItem::NewGame,
+		PauseMenuItem::DailyChallenge,
+	];
+
+	fn label(&self) -> &'static str {
+		match self {
+			PauseMenuItem::Resume => "Resume",
+			PauseMenuItem::RetryBoard => "Retry same board",
+			PauseMenuItem::NewGame => "New game",
+			PauseMenuItem::DailyChallenge => "Daily challenge",
 		}
 	}
 }
@@ -0,0 +1,138 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+
+/// Upper limit for `MixerSettings::max_voices`, so the audio thread can allocate space for every voice up front.
+pub const MAX_VOICES: usize = 32;
+
+
+/// Groups of sounds that share a volume control.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum SoundCategory {
+	/// Clicks, flags and other direct responses to input.
+	Feedback,
+
+	/// Winning and losing.
+	Events,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+pub struct ChannelMix {
+	pub volume: f32,
+	pub muted: bool,
+}
+
+impl ChannelMix {
+	pub fn gain(&self) -> f32 {
+		if self.muted { 0.0 } else { self.volume }
+	}
+
+	fn ui(&mut self, ui: &mut egui::Ui, label: &str) {
+		ui.horizontal(|ui| {
+			ui.add(egui::Slider::new(&mut self.volume, 0.0..=1.0).text(label));
+			ui.checkbox(&mut self.muted, "Mute");
+		});
+	}
+}
+
+impl Default for ChannelMix {
+	fn default() -> Self {
+		ChannelMix {
+			volume: 1.0,
+			muted: false,
+		}
+	}
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(default)]
+pub struct MixerSettings {
+	pub master: ChannelMix,
+	pub feedback: ChannelMix,
+	pub events: ChannelMix,
+
+	/// Generative background music. Off by default.
+	pub music_enabled: bool,
+	pub music: ChannelMix,
+
+	/// How many sounds can play at once. Starting a sound past this stops the oldest one.
+	pub max_voices: usize,
+}
+
+impl Default for MixerSettings {
+	fn default() -> Self {
+		MixerSettings {
+			master: ChannelMix { volume: 0.3, muted: false },
+			feedback: ChannelMix::default(),
+			events: ChannelMix::default(),
+			music_enabled: false,
+			music: ChannelMix { volume: 0.5, muted: false },
+			max_voices: 8,
+		}
+	}
+}
+
+impl MixerSettings {
+	pub fn category(&self, category: SoundCategory) -> ChannelMix {
+		match category {
+			SoundCategory::Feedback => self.feedback,
+			SoundCategory::Events => self.events,
+		}
+	}
+
+	pub fn ui(&mut self, ui: &mut egui::Ui) {
+		self.master.ui(ui, "Master");
+		self.feedback.ui(ui, "Feedback");
+		self.events.ui(ui, "Events");
+
+		ui.checkbox(&mut self.music_enabled, "Music");
+		ui.add_enabled_ui(self.music_enabled, |ui| self.music.ui(ui, "Music"));
+
+		ui.add(egui::Slider::new(&mut self.max_voices, 1..=MAX_VOICES).text("Max voices"));
+	}
+}
+
+
+/// Keeps the output bus out of clipping by instantly ducking loud peaks and recovering smoothly,
+/// rather than hard clipping when many sounds stack up.
+pub struct Limiter {
+	gain: f32,
+	release_rate: f32,
+}
+
+impl Limiter {
+	const THRESHOLD: f32 = 0.9;
+	const RELEASE_TIME: f64 = 0.15;
+
+	pub fn new() -> Limiter {
+		Limiter {
+			gain: 1.0,
+			release_rate: 0.0,
+		}
+	}
+
+	pub fn set_sample_rate(&mut self, sample_rate: u32) {
+		self.release_rate = (sample_rate as f64 * Self::RELEASE_TIME).recip() as f32;
+	}
+
+	/// Processes an interleaved stereo buffer in place.
+	pub fn process(&mut self, buffer: &mut [f32]) {
+		for [l, r] in buffer.array_chunks_mut() {
+			let peak = l.abs().max(r.abs());
+
+			// Instant attack, linear release.
+			let target = if peak > Self::THRESHOLD { Self::THRESHOLD / peak } else { 1.0 };
+			self.gain = if target < self.gain {
+				target
+			} else {
+				(self.gain + self.release_rate).min(target)
+			};
+
+			*l *= self.gain;
+			*r *= self.gain;
+		}
+	}
+}
+
@@ -0,0 +1,212 @@
+use crate::patch::{SynthPatch, SynthVoice, load_patch};
+use crate::resource::resource_path;
+use crate::sound::semitones;
+
+
+/// What the game is doing, as far as the music is concerned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MusicState {
+	/// How much of the board has been cleared, from 0 to 1. More layers come in as this rises.
+	pub intensity: f32,
+
+	/// False once the game is over, which fades the music out.
+	pub playing: bool,
+}
+
+impl Default for MusicState {
+	fn default() -> Self {
+		MusicState {
+			intensity: 0.0,
+			playing: true,
+		}
+	}
+}
+
+
+/// Minor pentatonic, in semitones from the root.
+const SCALE: [i32; 5] = [0, 3, 5, 7, 10];
+
+/// One chord root per bar: i - VI - III - VII.
+const PROGRESSION: [i32; 4] = [0, -4, 3, -2];
+
+const TEMPO: f64 = 108.0;
+const STEPS_PER_BEAT: f64 = 4.0;
+const STEPS_PER_BAR: usize = 16;
+const MAX_NOTES: usize = 12;
+
+/// How far music is turned down while effects are playing.
+const DUCK_AMOUNT: f32 = 0.6;
+const DUCK_ATTACK_TIME: f64 = 0.02;
+const DUCK_RELEASE_TIME: f64 = 0.4;
+const FADE_OUT_TIME: f64 = 1.5;
+const FADE_IN_TIME: f64 = 0.5;
+
+
+#[derive(Debug, Copy, Clone)]
+enum Instrument {
+	Bass,
+	Lead,
+	Tick,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Note {
+	instrument: Instrument,
+	voice: SynthVoice,
+	frequency_scale: f64,
+	gain_left: f32,
+	gain_right: f32,
+}
+
+
+/// A step sequencer that makes up patterns as it goes, using the same patches and voices as sound effects.
+/// Runs on the audio thread, so doesn't allocate once created.
+pub struct Sequencer {
+	bass: SynthPatch,
+	lead: SynthPatch,
+	tick: SynthPatch,
+
+	notes: [Option<Note>; MAX_NOTES],
+
+	state: MusicState,
+	step_phase: f64,
+	step: usize,
+	bar: usize,
+	rng: u32,
+
+	/// Fades out on game over, and back in when a new game starts.
+	fade: f32,
+	duck: f32,
+}
+
+impl Sequencer {
+	pub fn new() -> Sequencer {
+		Sequencer {
+			bass: load_patch(&resource_path("sounds/music/bass.toml"), include_str!("../resource/sounds/music/bass.toml")),
+			lead: load_patch(&resource_path("sounds/music/lead.toml"), include_str!("../resource/sounds/music/lead.toml")),
+			tick: load_patch(&resource_path("sounds/music/tick.toml"), include_str!("../resource/sounds/music/tick.toml")),
+
+			notes: [None; MAX_NOTES],
+
+			state: MusicState::default(),
+			step_phase: 0.0,
+			step: 0,
+			bar: 0,
+			rng: 0x9e3779b9,
+
+			fade: 0.0,
+			duck: 1.0,
+		}
+	}
+
+	pub fn set_state(&mut self, state: MusicState) {
+		// Start again from the top when a new game starts.
+		if state.playing && !self.state.playing {
+			self.step_phase = 0.0;
+			self.step = 0;
+			self.bar = 0;
+		}
+
+		self.state = state;
+	}
+
+	/// Mixes music into an interleaved stereo buffer. `effects_playing` ducks the music so effects stay clear.
+	pub fn fill(&mut self, buffer: &mut [f32], dt: f64, gain: f32, effects_playing: bool) {
+		let duck_target = if effects_playing { 1.0 - DUCK_AMOUNT } else { 1.0 };
+		let duck_rate = (if duck_target < self.duck { dt / DUCK_ATTACK_TIME } else { dt / DUCK_RELEASE_TIME }) as f32;
+
+		let fade_target = if self.state.playing { 1.0 } else { 0.0 };
+		let fade_rate = (if self.state.playing { dt / FADE_IN_TIME } else { dt / FADE_OUT_TIME }) as f32;
+
+		for [l, r] in buffer.array_chunks_mut() {
+			// Don't start new notes once faded out, but let the ones already going ring out.
+			if self.state.playing || self.fade > 0.0 {
+				self.step_phase += dt * TEMPO / 60.0 * STEPS_PER_BEAT;
+				if self.step_phase >= 1.0 {
+					self.step_phase -= 1.0;
+					self.trigger_step();
+				}
+			}
+
+			self.duck += (duck_target - self.duck).clamp(-duck_rate, duck_rate);
+			self.fade += (fade_target - self.fade).clamp(-fade_rate, fade_rate);
+
+			let output_gain = gain * self.duck * self.fade;
+
+			for slot in self.notes.iter_mut() {
+				let Some(note) = slot.as_mut() else { continue };
+
+				let patch = match note.instrument {
+					Instrument::Bass => &self.bass,
+					Instrument::Lead => &self.lead,
+					Instrument::Tick => &self.tick,
+				};
+
+				let sample = note.voice.next_sample_transposed(patch, dt, note.frequency_scale) * output_gain;
+				*l += sample * note.gain_left;
+				*r += sample * note.gain_right;
+
+				if note.voice.is_finished() {
+					*slot = None;
+				}
+			}
+		}
+	}
+
+	fn trigger_step(&mut self) {
+		let step = self.step;
+		let intensity = self.state.intensity;
+		let root = PROGRESSION[self.bar % PROGRESSION.len()];
+
+		// Bass on the one and three, with pickups once things get going.
+		let bass_steps: &[usize] = if intensity > 0.5 { &[0, 6, 8, 14] } else { &[0, 8] };
+		if bass_steps.contains(&step) {
+			self.play(Instrument::Bass, root, 0.0);
+		}
+
+		// Arpeggios pick up from quarter to eighth notes.
+		let lead_interval = if intensity > 0.6 { 2 } else { 4 };
+		if intensity > 0.2 && step % lead_interval == 0 {
+			let degree = SCALE[self.next_random() as usize % SCALE.len()];
+			let octave = 12 * (self.next_random() % 2) as i32;
+			let pan = (self.next_random() % 1000) as f32 / 1000.0 * 0.8 - 0.4;
+			self.play(Instrument::Lead, root + degree + octave, pan);
+		}
+
+		// Offbeat ticks, then sixteenths near the end of the game.
+		let tick_interval = if intensity > 0.8 { 1 } else { 4 };
+		if intensity > 0.4 && (step + 2) % tick_interval == 0 {
+			self.play(Instrument::Tick, 0, 0.3);
+		}
+
+		self.step = (self.step + 1) % STEPS_PER_BAR;
+		if self.step == 0 {
+			self.bar += 1;
+		}
+	}
+
+	fn play(&mut self, instrument: Instrument, note: i32, pan: f32) {
+		// Cut off the first note if there are no free slots. Notes are short, so this is rare.
+		let slot = self.notes.iter().position(Option::is_none).unwrap_or(0);
+
+		let pan_angle = (pan + 1.0) * std::f32::consts::FRAC_PI_4;
+
+		self.notes[slot] = Some(Note {
+			instrument,
+			voice: SynthVoice::default(),
+			frequency_scale: semitones(note as f32) as f64,
+			gain_left: pan_angle.cos() * std::f32::consts::SQRT_2,
+			gain_right: pan_angle.sin() * std::f32::consts::SQRT_2,
+		});
+	}
+
+	/// xorshift32. Good enough for picking notes, and doesn't need anything from the game thread.
+	fn next_random(&mut self) -> u32 {
+		let mut x = self.rng;
+		x ^= x << 13;
+		x ^= x >> 17;
+		x ^= x << 5;
+		self.rng = x;
+		x
+	}
+}
@@ -0,0 +1,182 @@
+use serde::{Serialize, de::DeserializeOwned};
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs, SocketAddr};
+use std::sync::mpsc::{channel, Sender, Receiver, TryRecvError};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+
+pub const DEFAULT_PORT: u16 = 7878;
+
+/// Spectators connect on their own port, so a game can be streamed while also hosting a multiplayer session.
+pub const SPECTATE_PORT: u16 = 7879;
+
+/// Short enough that an unreachable host doesn't freeze the game for long, and plenty for a LAN.
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+
+/// A TCP connection exchanging newline delimited JSON messages.
+/// Reading and writing happen on their own threads so the game never blocks on the network.
+pub struct Connection<In, Out> {
+	peer: SocketAddr,
+	outgoing: Sender<Out>,
+	incoming: Receiver<In>,
+	connected: Arc<AtomicBool>,
+}
+
+impl<In, Out> Connection<In, Out>
+	where In: DeserializeOwned + Send + 'static
+		, Out: Serialize + Send + 'static
+{
+	/// Gives up after `CONNECT_TIMEOUT` per address, since this is called from the game thread.
+	pub fn connect(address: impl ToSocketAddrs) -> anyhow::Result<Self> {
+		let mut last_error = None;
+
+		for address in address.to_socket_addrs()? {
+			match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+				Ok(stream) => return Connection::new(stream),
+				Err(error) => last_error = Some(error),
+			}
+		}
+
+		match last_error {
+			Some(error) => Err(error.into()),
+			None => anyhow::bail!("Address didn't resolve to anything"),
+		}
+	}
+
+	pub fn new(stream: TcpStream) -> anyhow::Result<Self> {
+		stream.set_nodelay(true)?;
+
+		let peer = stream.peer_addr()?;
+		let connected = Arc::new(AtomicBool::new(true));
+
+		let (outgoing, outgoing_rx) = channel::<Out>();
+		let (incoming_tx, incoming) = channel::<In>();
+
+		let reader = BufReader::new(stream.try_clone()?);
+		let mut writer = stream;
+
+		std::thread::Builder::new()
+			.name(format!("net read {peer}"))
+			.spawn({
+				let connected = Arc::clone(&connected);
+				move || {
+					for line in reader.lines() {
+						let Ok(line) = line else { break };
+
+						match serde_json::from_str(&line) {
+							Ok(message) => if incoming_tx.send(message).is_err() { break },
+							Err(error) => println!("[net] Ignoring malformed message from {peer}: {error}"),
+						}
+					}
+
+					connected.store(false, Ordering::Relaxed);
+				}
+			})?;
+
+		std::thread::Builder::new()
+			.name(format!("net write {peer}"))
+			.spawn({
+				let connected = Arc::clone(&connected);
+				move || {
+					for message in outgoing_rx {
+						let Ok(mut line) = serde_json::to_string(&message) else { continue };
+						line.push('\n');
+
+						if writer.write_all(line.as_bytes()).is_err() {
+							break
+						}
+					}
+
+					connected.store(false, Ordering::Relaxed);
+					let _ = writer.shutdown(std::net::Shutdown::Both);
+				}
+			})?;
+
+		Ok(Connection {
+			peer,
+			outgoing,
+			incoming,
+			connected,
+		})
+	}
+
+	pub fn send(&self, message: Out) {
+		if self.outgoing.send(message).is_err() {
+			self.connected.store(false, Ordering::Relaxed);
+		}
+	}
+
+	/// Takes the next message that has arrived, if any.
+	pub fn try_recv(&self) -> Option<In> {
+		match self.incoming.try_recv() {
+			Ok(message) => Some(message),
+			Err(TryRecvError::Empty) => None,
+			Err(TryRecvError::Disconnected) => {
+				self.connected.store(false, Ordering::Relaxed);
+				None
+			}
+		}
+	}
+
+	/// Drains every message that has arrived so far.
+	pub fn recv_all(&self) -> Vec<In> {
+		std::iter::from_fn(|| self.try_recv()).collect()
+	}
+
+	pub fn is_connected(&self) -> bool {
+		self.connected.load(Ordering::Relaxed)
+	}
+
+	pub fn peer(&self) -> SocketAddr {
+		self.peer
+	}
+}
+
+
+/// Accepts connections without blocking, to be polled once a frame.
+pub struct Listener {
+	listener: TcpListener,
+}
+
+impl Listener {
+	pub fn bind(address: impl ToSocketAddrs) -> anyhow::Result<Listener> {
+		let listener = TcpListener::bind(address)?;
+		listener.set_nonblocking(true)?;
+		Ok(Listener { listener })
+	}
+
+	pub fn local_addr(&self) -> Option<SocketAddr> {
+		self.listener.local_addr().ok()
+	}
+
+	pub fn accept<In, Out>(&self) -> Option<Connection<In, Out>>
+		where In: DeserializeOwned + Send + 'static
+			, Out: Serialize + Send + 'static
+	{
+		let (stream, _) = match self.listener.accept() {
+			Ok(accepted) => accepted,
+			Err(error) if error.kind() == std::io::ErrorKind::WouldBlock => return None,
+			Err(error) => {
+				println!("[net] Failed to accept connection: {error}");
+				return None
+			}
+		};
+
+		// Accepted streams inherit non-blocking mode on some platforms.
+		let connection = stream.set_nonblocking(false)
+			.map_err(anyhow::Error::from)
+			.and_then(|_| Connection::new(stream));
+
+		match connection {
+			Ok(connection) => Some(connection),
+			Err(error) => {
+				println!("[net] Failed to set up connection: {error}");
+				None
+			}
+		}
+	}
+}
@@ -0,0 +1,148 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+use crate::skin::SkinPalette;
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum PalettePreset {
+	/// Whatever the current skin defines.
+	Skin,
+	Deuteranopia,
+	Protanopia,
+	Tritanopia,
+	HighContrast,
+}
+
+impl PalettePreset {
+	pub const ALL: [PalettePreset; 5] = [
+		PalettePreset::Skin,
+		PalettePreset::Deuteranopia,
+		PalettePreset::Protanopia,
+		PalettePreset::Tritanopia,
+		PalettePreset::HighContrast,
+	];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			PalettePreset::Skin => "Skin",
+			PalettePreset::Deuteranopia => "Deuteranopia",
+			PalettePreset::Protanopia => "Protanopia",
+			PalettePreset::Tritanopia => "Tritanopia",
+			PalettePreset::HighContrast => "High contrast",
+		}
+	}
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct Palette {
+	pub board: Color,
+	pub unopened: Color,
+	pub hovered: Color,
+	pub glyph: Color,
+
+	/// Colours for each digit, drawn with the skin's untinted digit glyphs.
+	/// If None, the skin's own coloured digits are used.
+	pub digits: Option<[Color; 8]>,
+
+	/// Hatching drawn over flagged cells, so they can be told apart without relying on the flag glyph.
+	pub flag_pattern: Option<Color>,
+	pub hover_outline: Option<Color>,
+	pub wrong_outline: Color,
+
+	/// Background of the mine that ended the game.
+	pub detonated: Color,
+}
+
+impl Palette {
+	pub fn new(preset: PalettePreset, skin: &SkinPalette) -> Palette {
+		let skin_palette = Palette {
+			board: skin.board,
+			unopened: skin.unopened,
+			hovered: skin.hovered,
+			glyph: skin.glyph,
+
+			digits: None,
+			flag_pattern: None,
+			hover_outline: None,
+			wrong_outline: hex(0xe02b2b),
+			detonated: hex(0xc81e1e),
+		};
+
+		// Colour-blind presets keep the skin's background colours, but lean on outlines and patterns
+		// rather than brightness alone, and pick digit colours that stay distinct under each deficiency.
+		// Based on the Okabe-Ito and IBM colour-blind safe palettes.
+		match preset {
+			PalettePreset::Skin => skin_palette,
+
+			PalettePreset::Deuteranopia => Palette {
+				digits: Some([
+					hex(0x56b4e9), hex(0xe69f00), hex(0xd55e00), hex(0x0072b2),
+					hex(0xcc79a7), hex(0xf0e442), hex(0xffffff), hex(0x999999),
+				]),
+				flag_pattern: Some(hex(0xe69f00)),
+				hover_outline: Some(hex(0xffffff)),
+				wrong_outline: hex(0xd55e00),
+				..skin_palette
+			},
+
+			PalettePreset::Protanopia => Palette {
+				digits: Some([
+					hex(0x648fff), hex(0xffb000), hex(0xdc267f), hex(0x56b4e9),
+					hex(0xfe6100), hex(0xf0e442), hex(0xffffff), hex(0x999999),
+				]),
+				flag_pattern: Some(hex(0xffb000)),
+				hover_outline: Some(hex(0xffffff)),
+				wrong_outline: hex(0xfe6100),
+				..skin_palette
+			},
+
+			PalettePreset::Tritanopia => Palette {
+				digits: Some([
+					hex(0x00c2c2), hex(0xff5a5a), hex(0xffffff), hex(0xb30000),
+					hex(0xff9ec7), hex(0x008080), hex(0xd9d9d9), hex(0x808080),
+				]),
+				flag_pattern: Some(hex(0xff5a5a)),
+				hover_outline: Some(hex(0xffffff)),
+				wrong_outline: hex(0xff5a5a),
+				..skin_palette
+			},
+
+			PalettePreset::HighContrast => Palette {
+				board: hex(0x000000),
+				unopened: hex(0x5a5a5a),
+				hovered: hex(0x8c8c8c),
+				glyph: hex(0xffffff),
+
+				digits: Some([
+					hex(0x00ffff), hex(0x00ff00), hex(0xff4040), hex(0xffff00),
+					hex(0xff00ff), hex(0xffffff), hex(0xff8000), hex(0xc0c0c0),
+				]),
+				flag_pattern: Some(hex(0xffffff)),
+				hover_outline: Some(hex(0xffff00)),
+				wrong_outline: hex(0xff0000),
+				detonated: hex(0xff0000),
+			},
+		}
+	}
+}
+
+
+const PLAYER_COLORS: [u32; 6] = [0x0072b2, 0xe69f00, 0x009e73, 0xcc79a7, 0x56b4e9, 0xd55e00];
+
+/// Distinct colours for telling multiplayer players apart, picked to stay distinguishable under the
+/// common colour vision deficiencies.
+pub fn player_color(player_index: usize) -> Color {
+	hex(PLAYER_COLORS[player_index % PLAYER_COLORS.len()])
+}
+
+/// The same colour as `player_color`, for ui.
+pub fn player_color32(player_index: usize) -> egui::Color32 {
+	let [_, r, g, b] = PLAYER_COLORS[player_index % PLAYER_COLORS.len()].to_be_bytes();
+	egui::Color32::from_rgb(r, g, b)
+}
+
+fn hex(rgb: u32) -> Color {
+	let channel = |shift: u32| ((rgb >> shift) & 0xff) as f32 / 255.0;
+	Color::rgb(channel(16), channel(8), channel(0))
+}
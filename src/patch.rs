@@ -0,0 +1,276 @@
+use serde::Deserialize;
+
+use crate::sound::Sound;
+use crate::resource::{resource_path, FileWatcher};
+
+use std::f64::consts::TAU;
+use std::path::{Path, PathBuf};
+
+
+/// Describes a synthesized sound: a set of oscillators sharing one phase, with a frequency that
+/// changes over the course of a single amplitude envelope.
+///
+/// Everything that changes over time is driven by the envelope phase, which runs from 0 to 1 over
+/// `duration` seconds.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct SynthPatch {
+	/// Length of the amplitude envelope in seconds.
+	pub duration: f64,
+
+	/// Applied to the sum of all oscillators.
+	#[serde(default = "one")]
+	pub gain: f32,
+
+	/// Base frequency in Hz.
+	pub frequency: f64,
+
+	/// Added to the base frequency.
+	#[serde(default)]
+	pub frequency_mods: Vec<FrequencyMod>,
+
+	/// Phase is wrapped to this range each sample if set. Only matters for precision.
+	#[serde(default)]
+	pub phase_wrap: Option<f64>,
+
+	pub oscillators: Vec<Oscillator>,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum FrequencyMod {
+	/// `amount * (env - start)^exponent`, once the envelope has passed `start`.
+	Power {
+		amount: f64,
+		exponent: i32,
+		#[serde(default)]
+		start: f64,
+	},
+
+	/// `amount * sin(env * rate)`.
+	Sine {
+		amount: f64,
+		rate: f64,
+	},
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Oscillator {
+	#[serde(default)]
+	pub waveform: Waveform,
+
+	/// Multiple of the patch frequency, plus `ratio_slope` per unit of envelope phase.
+	#[serde(default = "one")]
+	pub ratio: f64,
+	#[serde(default)]
+	pub ratio_slope: f64,
+
+	#[serde(default = "one")]
+	pub gain: f32,
+
+	/// Delays this oscillator's amplitude envelope, in units of envelope phase.
+	#[serde(default)]
+	pub envelope_offset: f64,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Waveform {
+	#[default]
+	Sine,
+	Triangle,
+	Square,
+	Saw,
+}
+
+fn one<T: From<u8>>() -> T {
+	T::from(1)
+}
+
+
+impl SynthPatch {
+	pub fn parse(source: &str) -> anyhow::Result<SynthPatch> {
+		let patch: SynthPatch = toml::from_str(source)?;
+		anyhow::ensure!(patch.duration > 0.0, "Patch duration must be positive");
+		Ok(patch)
+	}
+
+	fn frequency_at(&self, env_phase: f64) -> f64 {
+		self.frequency_mods.iter()
+			.fold(self.frequency, |frequency, modulation| {
+				frequency + match *modulation {
+					FrequencyMod::Power { amount, exponent, start } => {
+						if env_phase < start {
+							0.0
+						} else {
+							amount * (env_phase - start).powi(exponent)
+						}
+					}
+
+					FrequencyMod::Sine { amount, rate } => amount * (env_phase * rate).sin(),
+				}
+			})
+	}
+}
+
+impl Waveform {
+	fn sample(&self, phase: f64) -> f32 {
+		let sample = match self {
+			Waveform::Sine => (phase * TAU).sin(),
+			Waveform::Triangle => 1.0 - 4.0 * (phase.rem_euclid(1.0) - 0.5).abs(),
+			Waveform::Square => if phase.rem_euclid(1.0) < 0.5 { 1.0 } else { -1.0 },
+			Waveform::Saw => 2.0 * phase.rem_euclid(1.0) - 1.0,
+		};
+
+		sample as f32
+	}
+}
+
+
+/// Playback state for a single instance of a patch.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SynthVoice {
+	phase: f64,
+	env_phase: f64,
+}
+
+impl SynthVoice {
+	pub fn next_sample(&mut self, patch: &SynthPatch, dt: f64) -> f32 {
+		self.next_sample_transposed(patch, dt, 1.0)
+	}
+
+	/// Like `next_sample`, but with every frequency scaled by `frequency_scale` without affecting timing.
+	pub fn next_sample_transposed(&mut self, patch: &SynthPatch, dt: f64, frequency_scale: f64) -> f32 {
+		let mut sample = 0.0;
+
+		for oscillator in patch.oscillators.iter() {
+			let env = gen_env(self.env_phase - oscillator.envelope_offset);
+			let ratio = oscillator.ratio + oscillator.ratio_slope * self.env_phase;
+			sample += oscillator.waveform.sample(self.phase * ratio) * oscillator.gain * env;
+		}
+
+		self.phase += dt * patch.frequency_at(self.env_phase) * frequency_scale;
+		if let Some(wrap) = patch.phase_wrap {
+			self.phase %= wrap;
+		}
+
+		self.env_phase += dt / patch.duration;
+
+		sample * patch.gain
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.env_phase > 1.0
+	}
+}
+
+
+pub fn gen_env(phase: f64) -> f32 {
+	if phase < 0.0 || phase > 1.0 {
+		return 0.0
+	}
+
+	if phase < 0.02 {
+		1.0 - (1.0 - phase/0.02).powi(2).clamp(0.0, 1.0) as f32
+
+	} else {
+		(1.0 - phase/0.98).powi(3).clamp(0.0, 1.0) as f32
+	}
+}
+
+
+/// A patch for every `Sound`, loaded from `resource/sounds/<name>.toml`.
+#[derive(Debug, Clone)]
+pub struct PatchBank {
+	patches: Vec<SynthPatch>,
+}
+
+impl PatchBank {
+	pub fn load() -> PatchBank {
+		let patches = Sound::ALL.iter()
+			.map(|&sound| load_patch(&patch_path(sound), builtin_source(sound)))
+			.collect();
+
+		PatchBank { patches }
+	}
+
+	pub fn watch(watcher: &mut FileWatcher) {
+		for sound in Sound::ALL {
+			watcher.watch(patch_path(sound));
+		}
+	}
+
+	pub fn get(&self, sound: Sound) -> &SynthPatch {
+		&self.patches[sound as usize]
+	}
+}
+
+/// Loads a patch file, falling back to the patch the game shipped with if it's missing or broken.
+pub fn load_patch(path: &Path, builtin_source: &str) -> SynthPatch {
+	let result = std::fs::read_to_string(path)
+		.map_err(anyhow::Error::from)
+		.and_then(|source| SynthPatch::parse(&source));
+
+	result.unwrap_or_else(|error| {
+		if path.exists() {
+			println!("[sound] Failed to load patch '{}': {error}", path.display());
+		}
+
+		SynthPatch::parse(builtin_source).expect("Builtin patch failed to parse")
+	})
+}
+
+fn patch_path(sound: Sound) -> PathBuf {
+	resource_path(format!("sounds/{}.toml", sound.name()))
+}
+
+fn builtin_source(sound: Sound) -> &'static str {
+	match sound {
+		Sound::Plik => include_str!("../resource/sounds/plik.toml"),
+		Sound::Bong => include_str!("../resource/sounds/bong.toml"),
+		Sound::Thup => include_str!("../resource/sounds/thup.toml"),
+		Sound::Unthup => include_str!("../resource/sounds/unthup.toml"),
+		Sound::Tada => include_str!("../resource/sounds/tada.toml"),
+		Sound::Hmm => include_str!("../resource/sounds/hmm.toml"),
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use super::*;
+	use crate::sound::render_sound;
+
+	/// FNV-1a over the samples quantized as they would be written to a WAV, so tiny floating point
+	/// differences between platforms don't matter but any audible change does.
+	fn checksum(samples: &[f32]) -> u64 {
+		samples.iter()
+			.flat_map(|&sample| ((sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16).to_le_bytes())
+			.fold(0xcbf29ce484222325, |hash, byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+	}
+
+	/// Catches unintended changes to the synth or the builtin patches. If a change is intended,
+	/// listen to the result with `export-sounds` and then update the checksums.
+	#[test]
+	fn builtin_sounds_render_unchanged() {
+		let expected = [
+			(Sound::Plik, 7350, 0x16cfb83ff3684765),
+			(Sound::Bong, 194040, 0xb81a20ae0dbb36e1),
+			(Sound::Thup, 4410, 0x4f8a0f9236a37ced),
+			(Sound::Unthup, 8820, 0x0efee53d6ef162d9),
+			(Sound::Tada, 88200, 0x8019865423f75239),
+			(Sound::Hmm, 22050, 0x8db87f517ebbb211),
+		];
+
+		assert_eq!(expected.len(), Sound::ALL.len());
+
+		for (sound, length, sum) in expected {
+			let patch = SynthPatch::parse(builtin_source(sound)).unwrap();
+			let samples = render_sound(&patch, 44100);
+
+			assert_eq!(samples.len(), length, "'{}' changed length", sound.name());
+			assert_eq!(checksum(&samples), sum, "'{}' changed", sound.name());
+		}
+	}
+}
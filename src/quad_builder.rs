@@ -1,4 +1,5 @@
 use toybox::prelude::*;
+use std::ops::Range;
 
 
 
@@ -18,13 +19,117 @@ impl QuadBuilder {
 		self.vertices.push(QuadVert{ pos: Vec2::new(max.x, min.y), color, uv: [255, 0], texture_index });
 	}
 
+	pub fn clear(&mut self) {
+		self.vertices.clear();
+		self.indices.clear();
+	}
+
+	pub fn num_quads(&self) -> usize {
+		self.vertices.len() / 4
+	}
+
 	pub fn finish(&mut self) {
-		let num_quads = self.vertices.len() / 4;
+		self.indices.extend(quad_indices(self.num_quads()))
+	}
+}
+
+
+fn quad_indices(num_quads: usize) -> impl Iterator<Item=u32> {
+	(0..num_quads as u32)
+		.flat_map(|idx| [0u32, 1, 2, 0, 2, 3].into_iter().map(move |base| base + idx*4))
+}
+
+
 
-		let indices = (0..num_quads as u32)
-			.flat_map(|idx| [0u32, 1, 2, 0, 2, 3].into_iter().map(move |base| base + idx*4));
+/// A fixed number of quad slots backed by persistent gpu buffers.
+/// Slots are rewritten on the cpu side and only the changed range is uploaded on flush.
+pub struct QuadBuffer {
+	vertices: Vec<QuadVert>,
+
+	vertex_buffer: gfx::BufferName,
+	index_buffer: gfx::BufferName,
+
+	dirty_quads: Option<Range<usize>>,
+}
+
+impl QuadBuffer {
+	pub fn new(gfx: &mut gfx::System, num_quads: usize) -> QuadBuffer {
+		let core = &mut gfx.core;
+
+		let mut buffer = QuadBuffer {
+			vertices: Vec::new(),
+			vertex_buffer: core.create_buffer(),
+			index_buffer: core.create_buffer(),
+			dirty_quads: None,
+		};
+
+		buffer.resize(gfx, num_quads);
+		buffer
+	}
+
+	pub fn num_quads(&self) -> usize {
+		self.vertices.len() / 4
+	}
+
+	pub fn num_elements(&self) -> u32 {
+		self.num_quads() as u32 * 6
+	}
 
-		self.indices.extend(indices)
+	pub fn vertex_buffer(&self) -> gfx::BufferName {
+		self.vertex_buffer
+	}
+
+	pub fn index_buffer(&self) -> gfx::BufferName {
+		self.index_buffer
+	}
+
+	/// Reallocates storage and regenerates indices. Only needs to happen when the board size changes.
+	pub fn resize(&mut self, gfx: &mut gfx::System, num_quads: usize) {
+		let core = &mut gfx.core;
+
+		self.vertices.clear();
+		self.vertices.resize(num_quads * 4, QuadVert::DEGENERATE);
+
+		let indices: Vec<u32> = quad_indices(num_quads).collect();
+
+		core.destroy_buffer(self.vertex_buffer);
+		core.destroy_buffer(self.index_buffer);
+
+		self.vertex_buffer = core.create_buffer();
+		self.index_buffer = core.create_buffer();
+
+		core.upload_immutable_buffer_immediate(self.index_buffer, &indices);
+		core.allocate_buffer_storage(self.vertex_buffer, std::mem::size_of_val(&self.vertices[..]), gfx::BufferUsage::Dynamic);
+
+		self.dirty_quads = Some(0..num_quads);
+	}
+
+	/// Overwrites `num_slots` quads starting at `first_quad` with the quads in `builder`.
+	/// Any slots not covered by `builder` are made degenerate.
+	pub fn write(&mut self, first_quad: usize, num_slots: usize, builder: &QuadBuilder) {
+		assert!(builder.num_quads() <= num_slots, "Too many quads for slot range");
+
+		let slot_vertices = &mut self.vertices[first_quad*4 .. (first_quad + num_slots)*4];
+		let (used, unused) = slot_vertices.split_at_mut(builder.vertices.len());
+		used.copy_from_slice(&builder.vertices);
+		unused.fill(QuadVert::DEGENERATE);
+
+		let range = first_quad .. first_quad + num_slots;
+		self.dirty_quads = match self.dirty_quads.take() {
+			Some(dirty) => Some(dirty.start.min(range.start) .. dirty.end.max(range.end)),
+			None => Some(range),
+		};
+	}
+
+	/// Uploads any rewritten quads, returning how many were uploaded.
+	pub fn flush(&mut self, gfx: &mut gfx::System) -> usize {
+		let Some(dirty) = self.dirty_quads.take() else { return 0 };
+
+		let vertices = &self.vertices[dirty.start*4 .. dirty.end*4];
+		let offset = dirty.start * 4 * std::mem::size_of::<QuadVert>();
+		gfx.core.upload_buffer_immediate(self.vertex_buffer, offset, vertices);
+
+		dirty.len()
 	}
 }
 
@@ -39,4 +144,13 @@ pub struct QuadVert {
 
 	uv: [u8; 2],
 	texture_index: u16,
-}
\ No newline at end of file
+}
+
+impl QuadVert {
+	const DEGENERATE: QuadVert = QuadVert {
+		pos: Vec2{ x: 0.0, y: 0.0 },
+		color: [0; 4],
+		uv: [0; 2],
+		texture_index: 0,
+	};
+}
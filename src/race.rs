@@ -0,0 +1,344 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::board::{Board, CellState, MAX_BOARD_SIZE};
+use crate::net::{Connection, Listener};
+
+
+/// Everyone races on the same board, seeded by the host.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct RaceStart {
+	pub seed: u64,
+	pub width: i32,
+	pub height: i32,
+	pub mines: usize,
+
+	/// Seconds from when the message is received until the race starts.
+	/// Relative rather than absolute, since clocks on different machines can't be trusted to agree.
+	pub countdown: f32,
+}
+
+impl RaceStart {
+	/// Whether a board can be made from this. Starts come from the host over the network, so are checked first.
+	pub fn is_valid(&self) -> bool {
+		let valid_size = |size| (1..=MAX_BOARD_SIZE).contains(&size);
+		valid_size(self.width) && valid_size(self.height)
+			&& self.mines <= (self.width * self.height) as usize
+	}
+
+	pub fn make_board(&self) -> Board {
+		Board::with_bombs(Vec2i::new(self.width, self.height), self.mines, self.seed)
+	}
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+pub enum RaceStatus {
+	Waiting,
+	Playing,
+	Finished,
+	Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlayerProgress {
+	pub player_id: u32,
+	pub name: String,
+	pub status: RaceStatus,
+
+	/// Fraction of safe cells opened.
+	pub cleared: f32,
+	pub flags: usize,
+
+	/// Seconds since the race started, frozen once finished or failed.
+	pub time: f32,
+
+	/// One character per cell, row by row from the bottom. See `encode_minimap`.
+	pub minimap: String,
+	pub minimap_width: i32,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum RaceMessage {
+	/// Client to host, on connecting.
+	Hello { name: String },
+
+	/// Host to client, in response to `Hello`.
+	Welcome { player_id: u32 },
+
+	/// Host to everyone.
+	Start(RaceStart),
+
+	/// Clients send their own progress to the host, which relays it to everyone else along with its own.
+	Progress(PlayerProgress),
+
+	/// Host to everyone, when a player disconnects.
+	Left { player_id: u32 },
+}
+
+type RaceConnection = Connection<RaceMessage, RaceMessage>;
+
+
+struct RemotePlayer {
+	player_id: u32,
+	connection: RaceConnection,
+}
+
+enum Role {
+	Host {
+		listener: Listener,
+		clients: Vec<RemotePlayer>,
+		next_player_id: u32,
+	},
+
+	Client {
+		connection: RaceConnection,
+	},
+}
+
+
+pub struct RaceSession {
+	role: Role,
+
+	pub name: String,
+	pub player_id: u32,
+
+	/// Everyone else's latest progress.
+	pub opponents: Vec<PlayerProgress>,
+
+	start: Option<RaceStart>,
+	last_sent_progress: Option<PlayerProgress>,
+}
+
+impl RaceSession {
+	pub const HOST_PLAYER_ID: u32 = 0;
+
+	pub fn host(port: u16, name: String) -> anyhow::Result<RaceSession> {
+		let listener = Listener::bind(("0.0.0.0", port))?;
+
+		if let Some(address) = listener.local_addr() {
+			println!("[race] Hosting on {address}");
+		}
+
+		Ok(RaceSession {
+			role: Role::Host {
+				listener,
+				clients: Vec::new(),
+				next_player_id: Self::HOST_PLAYER_ID + 1,
+			},
+
+			name,
+			player_id: Self::HOST_PLAYER_ID,
+			opponents: Vec::new(),
+			start: None,
+			last_sent_progress: None,
+		})
+	}
+
+	pub fn join(address: &str, name: String) -> anyhow::Result<RaceSession> {
+		let connection = RaceConnection::connect(address)?;
+		connection.send(RaceMessage::Hello { name: name.clone() });
+
+		println!("[race] Joined {}", connection.peer());
+
+		Ok(RaceSession {
+			role: Role::Client { connection },
+
+			name,
+			player_id: u32::MAX,
+			opponents: Vec::new(),
+			start: None,
+			last_sent_progress: None,
+		})
+	}
+
+	pub fn is_host(&self) -> bool {
+		matches!(self.role, Role::Host{..})
+	}
+
+	/// False once a client has lost its connection to the host.
+	pub fn is_connected(&self) -> bool {
+		match &self.role {
+			Role::Host{..} => true,
+			Role::Client { connection } => connection.is_connected(),
+		}
+	}
+
+	pub fn num_players(&self) -> usize {
+		match &self.role {
+			Role::Host { clients, .. } => clients.len() + 1,
+			Role::Client{..} => self.opponents.len() + 1,
+		}
+	}
+
+	/// Host only. Tells everyone, including the host, to start racing.
+	pub fn start_race(&mut self, start: RaceStart) {
+		let Role::Host { clients, .. } = &self.role else { return };
+
+		for client in clients.iter() {
+			client.connection.send(RaceMessage::Start(start));
+		}
+
+		self.start = Some(start);
+	}
+
+	/// Returns a race start if one has arrived since the last call.
+	pub fn take_start(&mut self) -> Option<RaceStart> {
+		self.start.take()
+	}
+
+	/// Handles incoming messages and connections. Should be called once a frame.
+	pub fn update(&mut self) {
+		match &mut self.role {
+			Role::Host { listener, clients, next_player_id } => {
+				while let Some(connection) = listener.accept() {
+					println!("[race] {} connected", connection.peer());
+
+					let player_id = *next_player_id;
+					*next_player_id += 1;
+
+					connection.send(RaceMessage::Welcome { player_id });
+
+					// Catch the new player up with everyone else.
+					for progress in self.opponents.iter().chain(&self.last_sent_progress) {
+						connection.send(RaceMessage::Progress(progress.clone()));
+					}
+
+					clients.push(RemotePlayer { player_id, connection });
+				}
+
+				let mut relayed = Vec::new();
+
+				for client in clients.iter() {
+					for message in client.connection.recv_all() {
+						match message {
+							// Only trust player ids the host handed out.
+							RaceMessage::Progress(progress) if progress.player_id == client.player_id => {
+								upsert_progress(&mut self.opponents, progress.clone());
+								relayed.push((client.player_id, RaceMessage::Progress(progress)));
+							}
+
+							RaceMessage::Hello { name } => println!("[race] Player {} is '{name}'", client.player_id),
+							_ => {}
+						}
+					}
+				}
+
+				let (connected, disconnected): (Vec<_>, Vec<_>) = clients.drain(..)
+					.partition(|client| client.connection.is_connected());
+
+				*clients = connected;
+
+				for client in disconnected {
+					println!("[race] Player {} disconnected", client.player_id);
+					self.opponents.retain(|progress| progress.player_id != client.player_id);
+					relayed.push((client.player_id, RaceMessage::Left { player_id: client.player_id }));
+				}
+
+				for (source_id, message) in relayed {
+					for client in clients.iter().filter(|client| client.player_id != source_id) {
+						client.connection.send(message.clone());
+					}
+				}
+			}
+
+			Role::Client { connection } => {
+				for message in connection.recv_all() {
+					match message {
+						RaceMessage::Welcome { player_id } => self.player_id = player_id,
+						RaceMessage::Start(start) if start.is_valid() => self.start = Some(start),
+						RaceMessage::Start(start) => println!("[race] Ignoring invalid race start {start:?}"),
+
+						RaceMessage::Progress(progress) if progress.player_id != self.player_id => {
+							upsert_progress(&mut self.opponents, progress);
+						}
+
+						RaceMessage::Left { player_id } => self.opponents.retain(|progress| progress.player_id != player_id),
+						_ => {}
+					}
+				}
+			}
+		}
+	}
+
+	/// Sends local progress to everyone else, if it's changed since last time.
+	pub fn send_progress(&mut self, progress: PlayerProgress) {
+		if self.last_sent_progress.as_ref() == Some(&progress) {
+			return
+		}
+
+		match &self.role {
+			Role::Host { clients, .. } => {
+				for client in clients.iter() {
+					client.connection.send(RaceMessage::Progress(progress.clone()));
+				}
+			}
+
+			Role::Client { connection } => connection.send(RaceMessage::Progress(progress.clone())),
+		}
+
+		self.last_sent_progress = Some(progress);
+	}
+}
+
+fn upsert_progress(players: &mut Vec<PlayerProgress>, progress: PlayerProgress) {
+	match players.iter_mut().find(|existing| existing.player_id == progress.player_id) {
+		Some(existing) => *existing = progress,
+		None => {
+			players.push(progress);
+			players.sort_by_key(|progress| progress.player_id);
+		}
+	}
+}
+
+
+/// Encodes cell states for the opponent mini-map, one character per cell.
+pub fn encode_minimap(board: &Board) -> String {
+	board.states.iter_with_positions()
+		.map(|(position, state)| match state {
+			_ if board.detonated == Some(position) => 'x',
+			CellState::Unopened => '.',
+			CellState::Opened => 'o',
+			CellState::Flagged => 'f',
+			CellState::Questioned => '?',
+		})
+		.collect()
+}
+
+/// Draws an opponent's board in the space available, in a few flat colours.
+pub fn draw_minimap(ui: &mut egui::Ui, progress: &PlayerProgress) {
+	let minimap = &progress.minimap;
+	let width = progress.minimap_width.max(0) as usize;
+
+	// Progress comes from other players, so anything that isn't a whole grid is ignored rather than trusted.
+	if width == 0 || minimap.is_empty() || !minimap.is_ascii() || minimap.len() % width != 0 {
+		return
+	}
+
+	let height = minimap.len() / width;
+
+	let cell_size = (ui.available_width() / width as f32).min(6.0);
+	let size = egui::vec2(cell_size * width as f32, cell_size * height as f32);
+	let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+
+	let painter = ui.painter_at(rect);
+
+	for (index, cell) in minimap.chars().enumerate() {
+		let x = (index % width) as f32;
+		let y = (height - 1 - index / width) as f32;
+
+		let color = match cell {
+			'o' => egui::Color32::from_gray(200),
+			'f' => egui::Color32::from_rgb(230, 80, 60),
+			'x' => egui::Color32::from_rgb(255, 220, 40),
+			'?' => egui::Color32::from_rgb(90, 120, 220),
+			_ => egui::Color32::from_gray(70),
+		};
+
+		let min = rect.min + egui::vec2(x * cell_size, y * cell_size);
+		let cell_rect = egui::Rect::from_min_size(min, egui::vec2(cell_size, cell_size)).shrink(0.5);
+		painter.rect_filled(cell_rect, 0.0, color);
+	}
+}
@@ -0,0 +1,88 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::resource::{user_data_path, write_user_data};
+use crate::daily::DailyResult;
+
+
+const RECORDS_FILE: &str = "records.toml";
+
+
+/// Personal bests, kept per board configuration.
+#[derive(Serialize, Deserialize, Default, Debug)]
+pub struct Records {
+	#[serde(default)]
+	best_times: Vec<BestTime>,
+
+	#[serde(default)]
+	daily: Vec<DailyResult>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+struct BestTime {
+	width: i32,
+	height: i32,
+	mines: usize,
+	seconds: f32,
+}
+
+impl Records {
+	pub fn load() -> Records {
+		let Ok(contents) = std::fs::read_to_string(user_data_path(RECORDS_FILE)) else {
+			return Records::default()
+		};
+
+		toml::from_str(&contents)
+			.unwrap_or_else(|error| {
+				println!("[records] Failed to parse records: {error}");
+				Records::default()
+			})
+	}
+
+	pub fn save(&self) -> anyhow::Result<()> {
+		write_user_data(RECORDS_FILE, toml::to_string(self)?)
+	}
+
+	pub fn best_time(&self, size: Vec2i, mines: usize) -> Option<f32> {
+		self.best_times.iter()
+			.find(|best| best.width == size.x && best.height == size.y && best.mines == mines)
+			.map(|best| best.seconds)
+	}
+
+	pub fn daily_result(&self, date: &str) -> Option<&DailyResult> {
+		self.daily.iter().find(|result| result.date == date)
+	}
+
+	/// Inserts or replaces the result for a daily challenge.
+	pub fn submit_daily(&mut self, result: DailyResult) {
+		match self.daily.iter_mut().find(|existing| existing.date == result.date) {
+			Some(existing) => *existing = result,
+			None => self.daily.push(result),
+		}
+	}
+
+	/// Records a finishing time, returning true if it's a new personal best.
+	pub fn submit_time(&mut self, size: Vec2i, mines: usize, seconds: f32) -> bool {
+		let existing = self.best_times.iter_mut()
+			.find(|best| best.width == size.x && best.height == size.y && best.mines == mines);
+
+		match existing {
+			Some(best) if best.seconds <= seconds => false,
+			Some(best) => {
+				best.seconds = seconds;
+				true
+			}
+
+			None => {
+				self.best_times.push(BestTime {
+					width: size.x,
+					height: size.y,
+					mines,
+					seconds,
+				});
+
+				true
+			}
+		}
+	}
+}
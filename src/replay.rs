@@ -0,0 +1,75 @@
+use toybox::prelude::*;
+
+use crate::view::CellResponse;
+use crate::board::Board;
+
+
+/// Everything needed to reproduce a game: the board it was played on, and what the player did.
+#[derive(Debug, Clone)]
+pub struct Replay {
+	pub board_size: Vec2i,
+	pub seed: u64,
+
+	/// Bomb positions at the start of the game. Stored explicitly rather than regenerated from the seed,
+	/// since retried boards may have had bombs moved by a first click.
+	pub bombs: Vec<Vec2i>,
+
+	pub actions: Vec<ReplayAction>,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct ReplayAction {
+	/// Seconds since the start of the game.
+	pub time: f32,
+	pub position: Vec2i,
+	pub response: CellResponse,
+}
+
+impl Replay {
+	pub fn new(board: &Board) -> Replay {
+		Replay {
+			board_size: board.size(),
+			seed: board.seed(),
+			bombs: board.bomb_positions(),
+			actions: Vec::new(),
+		}
+	}
+
+	pub fn make_board(&self) -> Board {
+		Board::from_bombs(self.board_size, &self.bombs, self.seed)
+	}
+
+	pub fn record(&mut self, time: f32, position: Vec2i, response: CellResponse) {
+		self.actions.push(ReplayAction{time, position, response});
+	}
+}
+
+
+pub struct ReplayPlayback {
+	pub replay: Replay,
+	next_action: usize,
+}
+
+impl ReplayPlayback {
+	pub fn new(replay: Replay) -> ReplayPlayback {
+		ReplayPlayback {
+			replay,
+			next_action: 0,
+		}
+	}
+
+	/// Returns the next action if it's due by `time`.
+	pub fn next_due(&mut self, time: f32) -> Option<ReplayAction> {
+		let action = *self.replay.actions.get(self.next_action)?;
+		if action.time > time {
+			return None
+		}
+
+		self.next_action += 1;
+		Some(action)
+	}
+
+	pub fn is_finished(&self) -> bool {
+		self.next_action >= self.replay.actions.len()
+	}
+}
@@ -0,0 +1,98 @@
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+use std::time::{Instant, Duration, SystemTime};
+
+
+/// Finds the `resource` directory by searching up from the working directory and then the executable.
+pub fn resource_root() -> &'static Path {
+	static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+	ROOT.get_or_init(|| {
+		let working_dir = std::env::current_dir().ok();
+		let exe_dir = std::env::current_exe().ok()
+			.and_then(|path| path.parent().map(Path::to_owned));
+
+		working_dir.into_iter().chain(exe_dir)
+			.flat_map(|dir| dir.ancestors().map(|dir| dir.join("resource")).collect::<Vec<_>>())
+			.find(|dir| dir.is_dir())
+			.unwrap_or_else(|| PathBuf::from("resource"))
+	})
+}
+
+pub fn resource_path(relative: impl AsRef<Path>) -> PathBuf {
+	resource_root().join(relative)
+}
+
+
+
+/// Polls modification times of a set of files so they can be hot reloaded.
+pub struct FileWatcher {
+	files: Vec<(PathBuf, Option<SystemTime>)>,
+	last_poll: Instant,
+}
+
+impl FileWatcher {
+	const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+	pub fn new() -> FileWatcher {
+		FileWatcher {
+			files: Vec::new(),
+			last_poll: Instant::now(),
+		}
+	}
+
+	pub fn watch(&mut self, path: impl Into<PathBuf>) {
+		let path = path.into();
+		let modified = modified_time(&path);
+		self.files.push((path, modified));
+	}
+
+	/// Returns true if any watched file has changed since the last call.
+	pub fn poll(&mut self) -> bool {
+		if self.last_poll.elapsed() < Self::POLL_INTERVAL {
+			return false
+		}
+
+		self.last_poll = Instant::now();
+
+		let mut changed = false;
+
+		for (path, last_modified) in self.files.iter_mut() {
+			let modified = modified_time(path);
+			if modified != *last_modified {
+				*last_modified = modified;
+				changed = true;
+			}
+		}
+
+		changed
+	}
+}
+
+fn modified_time(path: &Path) -> Option<SystemTime> {
+	std::fs::metadata(path).and_then(|meta| meta.modified()).ok()
+}
+
+
+
+/// Where per-user files like settings and records are kept.
+pub fn user_data_path(relative: impl AsRef<Path>) -> PathBuf {
+	let base = std::env::var_os("XDG_DATA_HOME").map(PathBuf::from)
+		.or_else(|| std::env::var_os("APPDATA").map(PathBuf::from))
+		.or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share")))
+		.unwrap_or_else(|| PathBuf::from("."));
+
+	base.join("myneswooper").join(relative)
+}
+
+/// Writes a file in the user data directory, creating the directory if needed.
+pub fn write_user_data(relative: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> anyhow::Result<()> {
+	let path = user_data_path(relative);
+
+	if let Some(parent) = path.parent() {
+		std::fs::create_dir_all(parent)?;
+	}
+
+	std::fs::write(path, contents)?;
+	Ok(())
+}
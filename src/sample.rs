@@ -0,0 +1,146 @@
+use crate::sound::Sound;
+use crate::resource::{resource_path, FileWatcher};
+use crate::wav::read_wav;
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+
+/// File types a sound can be replaced with, in order of preference.
+const SAMPLE_EXTENSIONS: [&str; 2] = ["wav", "ogg"];
+
+
+/// A decoded sound file as interleaved stereo, ready to be played on the audio thread as-is.
+#[derive(Debug)]
+pub struct SampleData {
+	pub sample_rate: u32,
+	pub frames: Vec<[f32; 2]>,
+}
+
+impl SampleData {
+	pub fn decode(path: &Path) -> anyhow::Result<SampleData> {
+		let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or("");
+
+		let (sample_rate, channels, samples) = match extension {
+			"wav" => {
+				let wav = read_wav(&std::fs::read(path)?)?;
+				(wav.sample_rate, wav.channels as usize, wav.samples)
+			}
+
+			"ogg" => decode_ogg(path)?,
+			_ => anyhow::bail!("Unsupported sample format '{extension}'"),
+		};
+
+		anyhow::ensure!(sample_rate > 0, "Sample has no sample rate");
+
+		// Mono is played on both sides, and anything past stereo is dropped.
+		let frames = samples.chunks_exact(channels)
+			.map(|frame| match *frame {
+				[mono] => [mono, mono],
+				[left, right, ..] => [left, right],
+				[] => unreachable!(),
+			})
+			.collect();
+
+		Ok(SampleData { sample_rate, frames })
+	}
+
+	/// Converts to another sample rate with linear interpolation.
+	pub fn resample(&self, target_rate: u32) -> SampleData {
+		if target_rate == self.sample_rate || self.frames.is_empty() {
+			return SampleData {
+				sample_rate: self.sample_rate,
+				frames: self.frames.clone(),
+			}
+		}
+
+		let step = self.sample_rate as f64 / target_rate as f64;
+		let num_frames = (self.frames.len() as f64 / step).floor() as usize;
+
+		let frames = (0..num_frames)
+			.map(|index| self.frame_at(index as f64 * step))
+			.collect();
+
+		SampleData {
+			sample_rate: target_rate,
+			frames,
+		}
+	}
+
+	/// Linearly interpolates between frames. Positions past the end are silent.
+	pub fn frame_at(&self, position: f64) -> [f32; 2] {
+		let index = position as usize;
+		let fraction = (position - index as f64) as f32;
+
+		let Some(&[l0, r0]) = self.frames.get(index) else { return [0.0; 2] };
+		let [l1, r1] = self.frames.get(index + 1).copied().unwrap_or([0.0; 2]);
+
+		[l0 + (l1 - l0) * fraction, r0 + (r1 - r0) * fraction]
+	}
+
+	pub fn num_frames(&self) -> usize {
+		self.frames.len()
+	}
+}
+
+fn decode_ogg(path: &Path) -> anyhow::Result<(u32, usize, Vec<f32>)> {
+	let mut reader = lewton::inside_ogg::OggStreamReader::new(std::fs::File::open(path)?)?;
+
+	let sample_rate = reader.ident_hdr.audio_sample_rate;
+	let channels = reader.ident_hdr.audio_channels as usize;
+	anyhow::ensure!(channels > 0, "Ogg stream has no channels");
+
+	let mut samples = Vec::new();
+	while let Some(packet) = reader.read_dec_packet_itl()? {
+		samples.extend(packet.into_iter().map(|sample| sample as f32 / 32768.0));
+	}
+
+	Ok((sample_rate, channels, samples))
+}
+
+
+/// Optional sample files replacing synthesized sounds, loaded from `resource/sounds/<name>.wav` or `.ogg`.
+/// Sounds without a file keep using their synth patch.
+#[derive(Debug, Default)]
+pub struct SampleBank {
+	samples: Vec<Option<Arc<SampleData>>>,
+}
+
+impl SampleBank {
+	/// Decodes and resamples every sample file. Slow, so this is best done on a background thread.
+	pub fn load(sample_rate: u32) -> SampleBank {
+		let samples = Sound::ALL.iter()
+			.map(|&sound| {
+				let path = sample_paths(sound).into_iter().find(|path| path.is_file())?;
+
+				match SampleData::decode(&path) {
+					Ok(sample) => Some(Arc::new(sample.resample(sample_rate))),
+					Err(error) => {
+						println!("[sound] Failed to load sample '{}': {error}", path.display());
+						None
+					}
+				}
+			})
+			.collect();
+
+		SampleBank { samples }
+	}
+
+	pub fn watch(watcher: &mut FileWatcher) {
+		for sound in Sound::ALL {
+			for path in sample_paths(sound) {
+				watcher.watch(path);
+			}
+		}
+	}
+
+	pub fn get(&self, sound: Sound) -> Option<&Arc<SampleData>> {
+		self.samples.get(sound as usize)?.as_ref()
+	}
+}
+
+fn sample_paths(sound: Sound) -> Vec<PathBuf> {
+	SAMPLE_EXTENSIONS.iter()
+		.map(|extension| resource_path(format!("sounds/{}.{extension}", sound.name())))
+		.collect()
+}
@@ -0,0 +1,100 @@
+use toybox::prelude::*;
+use crate::skin::Skin;
+use crate::palette::PalettePreset;
+use crate::touch::TouchSettings;
+use crate::mixer::{MixerSettings, MAX_VOICES};
+use crate::resource::{user_data_path, write_user_data};
+
+use serde::{Serialize, Deserialize};
+
+
+const SETTINGS_FILE: &str = "settings.toml";
+
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct Settings {
+	pub animations: bool,
+	pub question_marks: bool,
+
+	/// Whether retrying a board keeps a mine moved by the first click where it ended up,
+	/// or restores the original layout and moves it somewhere new.
+	pub retry_keeps_relocation: bool,
+
+	pub skin: String,
+	pub palette: PalettePreset,
+	pub touch: TouchSettings,
+	pub audio: MixerSettings,
+
+	/// Raise the pitch of reveals with flood fill distance and the number uncovered.
+	pub pitch_variation: bool,
+}
+
+impl Default for Settings {
+	fn default() -> Self {
+		Settings {
+			animations: true,
+			question_marks: true,
+			retry_keeps_relocation: true,
+			skin: Skin::DEFAULT.to_owned(),
+			palette: PalettePreset::Skin,
+			touch: TouchSettings::default(),
+			audio: MixerSettings::default(),
+			pitch_variation: true,
+		}
+	}
+}
+
+impl Settings {
+	pub fn load() -> Settings {
+		let Ok(contents) = std::fs::read_to_string(user_data_path(SETTINGS_FILE)) else {
+			return Settings::default()
+		};
+
+		let mut settings: Settings = toml::from_str(&contents)
+			.unwrap_or_else(|error| {
+				println!("[settings] Failed to parse settings: {error}");
+				Settings::default()
+			});
+
+		// The audio thread only has room for this many voices, so anything else in the file is ignored.
+		settings.audio.max_voices = settings.audio.max_voices.clamp(1, MAX_VOICES);
+		settings
+	}
+
+	pub fn save(&self) -> anyhow::Result<()> {
+		write_user_data(SETTINGS_FILE, toml::to_string(self)?)
+	}
+
+	pub fn ui(&mut self, ui: &mut egui::Ui, available_skins: &[String]) {
+		ui.checkbox(&mut self.animations, "Animations");
+		ui.checkbox(&mut self.question_marks, "Question marks");
+		ui.checkbox(&mut self.retry_keeps_relocation, "Retry keeps first click mine move");
+
+		egui::ComboBox::from_label("Skin")
+			.selected_text(&self.skin)
+			.show_ui(ui, |ui| {
+				for skin in available_skins {
+					ui.selectable_value(&mut self.skin, skin.clone(), skin);
+				}
+			});
+
+		egui::ComboBox::from_label("Palette")
+			.selected_text(self.palette.name())
+			.show_ui(ui, |ui| {
+				for preset in PalettePreset::ALL {
+					ui.selectable_value(&mut self.palette, preset, preset.name());
+				}
+			});
+
+		ui.collapsing("Touch", |ui| {
+			ui.add(egui::Slider::new(&mut self.touch.long_press_time, 0.15..=1.5).text("Long press (s)"));
+			ui.add(egui::Slider::new(&mut self.touch.tap_slop, 2.0..=48.0).text("Tap slop (pt)"));
+		});
+
+		ui.collapsing("Audio", |ui| {
+			self.audio.ui(ui);
+			ui.checkbox(&mut self.pitch_variation, "Pitch variation");
+		});
+	}
+}
@@ -0,0 +1,202 @@
+use toybox::prelude::*;
+use serde::{Deserialize, Deserializer};
+
+use crate::resource::{resource_path, FileWatcher};
+
+
+/// Describes the layout and colours of a skin. Loaded from `skins/<name>/skin.toml`.
+#[derive(Deserialize, Debug, Clone)]
+pub struct SkinManifest {
+	pub name: String,
+
+	/// Path to the atlas image, relative to the skin directory.
+	pub atlas: String,
+
+	pub cell_size: [u32; 2],
+	pub grid: [u32; 2],
+
+	pub glyphs: SkinGlyphs,
+	pub palette: SkinPalette,
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+pub struct SkinGlyphs {
+	pub blank: u16,
+	pub digits: [u16; 8],
+	pub flag: u16,
+	pub mine: u16,
+	pub question: u16,
+	pub wrong_flag: u16,
+
+	/// Untinted digits, for palettes that colour them.
+	pub mono_digits: [u16; 8],
+	pub outline: u16,
+	pub hatch: u16,
+}
+
+impl SkinGlyphs {
+	pub fn digit(&self, count: usize) -> u16 {
+		self.digits[count.clamp(1, 8) - 1]
+	}
+
+	pub fn mono_digit(&self, count: usize) -> u16 {
+		self.mono_digits[count.clamp(1, 8) - 1]
+	}
+}
+
+#[derive(Deserialize, Debug, Copy, Clone)]
+pub struct SkinPalette {
+	#[serde(deserialize_with="deserialize_color")]
+	pub board: Color,
+	#[serde(deserialize_with="deserialize_color")]
+	pub unopened: Color,
+	#[serde(deserialize_with="deserialize_color")]
+	pub hovered: Color,
+	#[serde(deserialize_with="deserialize_color")]
+	pub glyph: Color,
+}
+
+
+/// Layout of the atlas as seen by the main shader.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct AtlasUniforms {
+	cell_size: [u32; 2],
+	columns: u32,
+	_padding: u32,
+}
+
+
+pub struct Skin {
+	/// Name of the skin directory.
+	pub id: String,
+	pub manifest: SkinManifest,
+	/// Owned by the skin rather than the resource manager, which caches images by path and so would never
+	/// pick up edits. Must be freed with `destroy`.
+	pub atlas: gfx::ImageName,
+
+	watcher: FileWatcher,
+}
+
+impl Skin {
+	pub const DEFAULT: &'static str = "classic";
+
+	pub fn load(gfx: &mut gfx::System, id: &str) -> anyhow::Result<Skin> {
+		let manifest_path = resource_path(format!("skins/{id}/skin.toml"));
+		let manifest: SkinManifest = toml::from_str(&std::fs::read_to_string(&manifest_path)?)?;
+
+		anyhow::ensure!(manifest.grid[0] > 0 && manifest.grid[1] > 0, "Skin '{id}' has an empty atlas grid");
+
+		let atlas_path = resource_path(format!("skins/{id}/{}", manifest.atlas));
+		let atlas = load_atlas(gfx, &atlas_path)
+			.map_err(|error| anyhow::anyhow!("Failed to load atlas for skin '{id}': {error}"))?;
+
+		let mut watcher = FileWatcher::new();
+		watcher.watch(manifest_path);
+		watcher.watch(atlas_path);
+
+		Ok(Skin {
+			id: id.to_owned(),
+			manifest,
+			atlas,
+			watcher,
+		})
+	}
+
+	/// Lists the names of all skin directories.
+	pub fn available() -> Vec<String> {
+		let Ok(entries) = std::fs::read_dir(resource_path("skins")) else {
+			return Vec::new()
+		};
+
+		let mut skins: Vec<String> = entries
+			.filter_map(Result::ok)
+			.filter(|entry| entry.path().join("skin.toml").is_file())
+			.filter_map(|entry| entry.file_name().into_string().ok())
+			.collect();
+
+		skins.sort();
+		skins
+	}
+
+	/// Reloads the skin if any of its files have changed. Returns true if it was reloaded.
+	pub fn hot_reload(&mut self, gfx: &mut gfx::System) -> bool {
+		if !self.watcher.poll() {
+			return false
+		}
+
+		match Skin::load(gfx, &self.id) {
+			Ok(skin) => {
+				println!("[skin] Reloaded '{}'", self.id);
+				std::mem::replace(self, skin).destroy(gfx);
+				true
+			}
+
+			Err(error) => {
+				println!("[skin] Failed to reload '{}': {error}", self.id);
+				false
+			}
+		}
+	}
+
+	pub fn destroy(self, gfx: &mut gfx::System) {
+		gfx.core.destroy_image(self.atlas);
+	}
+
+	pub fn glyphs(&self) -> &SkinGlyphs {
+		&self.manifest.glyphs
+	}
+
+	pub fn palette(&self) -> &SkinPalette {
+		&self.manifest.palette
+	}
+
+	pub fn atlas_uniforms(&self) -> AtlasUniforms {
+		AtlasUniforms {
+			cell_size: self.manifest.cell_size,
+			columns: self.manifest.grid[0],
+			_padding: 0,
+		}
+	}
+}
+
+
+/// Decodes and uploads the atlas directly, so every load sees what's on disk.
+fn load_atlas(gfx: &mut gfx::System, path: &std::path::Path) -> anyhow::Result<gfx::ImageName> {
+	let image = image::open(path)?.into_rgba8();
+	let size = Vec2i::new(image.width() as i32, image.height() as i32);
+	let format = gfx::ImageFormat::Srgba8;
+
+	let core = &mut gfx.core;
+	let atlas = core.create_image_from_info(gfx::ImageInfo::image_2d(size, format));
+	core.upload_image(atlas, None, format, image.as_raw());
+
+	Ok(atlas)
+}
+
+
+fn deserialize_color<'de, D>(deserializer: D) -> Result<Color, D::Error>
+	where D: Deserializer<'de>
+{
+	use serde::de::Error;
+
+	let string = String::deserialize(deserializer)?;
+	parse_hex_color(&string)
+		.ok_or_else(|| D::Error::custom(format!("Invalid colour '{string}', expected #rrggbb or #rrggbbaa")))
+}
+
+fn parse_hex_color(string: &str) -> Option<Color> {
+	let hex = string.strip_prefix('#')?;
+	if !hex.is_ascii() {
+		return None
+	}
+
+	let channel = |idx: usize| u8::from_str_radix(hex.get(idx*2..idx*2+2)?, 16).ok()
+		.map(|value| value as f32 / 255.0);
+
+	match hex.len() {
+		6 => Some(Color::rgb(channel(0)?, channel(1)?, channel(2)?)),
+		8 => Some(Color::rgba(channel(0)?, channel(1)?, channel(2)?, channel(3)?)),
+		_ => None,
+	}
+}
@@ -1,44 +1,312 @@
 use toybox::prelude::*;
-use std::sync::mpsc::{SyncSender, Receiver, sync_channel};
+use std::sync::mpsc::{SyncSender, Receiver, sync_channel, TrySendError};
+use std::cell::Cell;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use crate::mixer::{MixerSettings, SoundCategory, Limiter, MAX_VOICES};
+use crate::patch::{PatchBank, SynthPatch, SynthVoice};
+use crate::sample::{SampleBank, SampleData};
+use crate::resource::FileWatcher;
+use crate::music::{Sequencer, MusicState};
+
+
+/// Commands that can be queued before the audio thread picks them up. Anything past this is dropped
+/// rather than blocking the game.
+const COMMAND_QUEUE_SIZE: usize = 64;
+
+/// Enough for everything one buffer can retire: a bank per command, and a sample per voice.
+const RETIRED_QUEUE_SIZE: usize = COMMAND_QUEUE_SIZE + 2 * MAX_VOICES;
 
 
 pub struct SoundSystem {
-	cmd_tx: SyncSender<ProviderCmd>,
+	/// None if there's no audio device, in which case everything is silently ignored.
+	cmd_tx: Option<SyncSender<ProviderCmd>>,
+
+	/// Resources the audio thread is done with, sent back so they're freed here instead of in the audio callback.
+	retired_rx: Option<Receiver<Retired>>,
+
+	stats: Cell<SoundStats>,
+
+	/// Patch files, so sounds can be tuned while the game is running.
+	patch_watcher: FileWatcher,
+
+	/// Sample files, which are decoded and resampled to match the device on a background thread.
+	sample_watcher: FileWatcher,
+	device_sample_rate: Arc<AtomicU32>,
+	loaded_sample_rate: u32,
+
+	/// Last state sent to the music sequencer, so it's only sent when it changes.
+	music_state: MusicState,
+}
+
+#[derive(Debug, Copy, Clone, Default)]
+pub struct SoundStats {
+	/// Commands dropped because the audio thread wasn't keeping up.
+	pub dropped_queue_full: usize,
+
+	/// Commands dropped because the audio provider went away, e.g., the device was unplugged.
+	pub dropped_disconnected: usize,
 }
 
 impl SoundSystem {
-	pub fn start(audio: &mut audio::System) -> anyhow::Result<SoundSystem> {
-		let (cmd_tx, cmd_rx) = sync_channel(4);
+	/// Starts sound on the audio device, falling back to a null backend if there isn't one or if
+	/// MYNESWOOPER_NULL_AUDIO is set.
+	pub fn start(audio: &mut audio::System) -> SoundSystem {
+		if std::env::var_os("MYNESWOOPER_NULL_AUDIO").is_some() {
+			println!("[sound] Using null audio backend");
+			return SoundSystem::null();
+		}
+
+		match SoundSystem::start_device(audio) {
+			Ok(system) => system,
+			Err(error) => {
+				println!("[sound] Failed to start audio, using null backend: {error}");
+				SoundSystem::null()
+			}
+		}
+	}
+
+	pub fn null() -> SoundSystem {
+		SoundSystem {
+			cmd_tx: None,
+			retired_rx: None,
+			stats: Cell::default(),
+			patch_watcher: FileWatcher::new(),
+			sample_watcher: FileWatcher::new(),
+			device_sample_rate: Arc::default(),
+			loaded_sample_rate: 0,
+			music_state: MusicState::default(),
+		}
+	}
+
+	fn start_device(audio: &mut audio::System) -> anyhow::Result<SoundSystem> {
+		let (cmd_tx, cmd_rx) = sync_channel(COMMAND_QUEUE_SIZE);
+		let (retired_tx, retired_rx) = sync_channel(RETIRED_QUEUE_SIZE);
+
+		let mut patch_watcher = FileWatcher::new();
+		PatchBank::watch(&mut patch_watcher);
+
+		let mut sample_watcher = FileWatcher::new();
+		SampleBank::watch(&mut sample_watcher);
+
+		// Samples can't be loaded until the device sample rate is known.
+		let device_sample_rate = Arc::new(AtomicU32::new(0));
 
 		let provider = Provider {
 			cmd_rx,
-			sounds: Vec::new(),
-			dt: 0.0
+			retired_tx,
+			sounds: Vec::with_capacity(MAX_VOICES),
+			scheduled: Vec::with_capacity(MAX_VOICES),
+			patches: Arc::new(PatchBank::load()),
+			samples: Arc::default(),
+			device_sample_rate: Arc::clone(&device_sample_rate),
+			sequencer: Sequencer::new(),
+			mix: MixerSettings::default(),
+			limiter: Limiter::new(),
+			dt: 0.0,
+			sample_rate: 0.0,
 		};
 
 		audio.set_provider(provider)?;
 
-		Ok(SoundSystem{cmd_tx})
+		Ok(SoundSystem {
+			cmd_tx: Some(cmd_tx),
+			retired_rx: Some(retired_rx),
+			stats: Cell::default(),
+			patch_watcher,
+			sample_watcher,
+			device_sample_rate,
+			loaded_sample_rate: 0,
+			music_state: MusicState::default(),
+		})
+	}
+
+	/// Reloads patches and samples if any have changed on disk, or if the device sample rate has changed.
+	pub fn update(&mut self) {
+		if let Some(retired_rx) = &self.retired_rx {
+			retired_rx.try_iter().for_each(drop);
+		}
+
+		if self.patch_watcher.poll() {
+			println!("[sound] Reloading patches");
+			self.send(ProviderCmd::SetPatches(Arc::new(PatchBank::load())));
+		}
+
+		let samples_changed = self.sample_watcher.poll();
+		let device_sample_rate = self.device_sample_rate.load(Ordering::Relaxed);
+
+		if device_sample_rate != 0 && (samples_changed || device_sample_rate != self.loaded_sample_rate) {
+			self.load_samples(device_sample_rate);
+		}
+	}
+
+	fn load_samples(&mut self, sample_rate: u32) {
+		let Some(cmd_tx) = self.cmd_tx.clone() else { return };
+
+		self.loaded_sample_rate = sample_rate;
+
+		// Decoding can take a while, so keep it off both the game and audio threads.
+		let result = std::thread::Builder::new()
+			.name("sample loader".into())
+			.spawn(move || {
+				let samples = SampleBank::load(sample_rate);
+
+				// Blocking is fine here, and the provider going away just means nobody needs the samples.
+				let _ = cmd_tx.send(ProviderCmd::SetSamples(Arc::new(samples)));
+			});
+
+		if let Err(error) = result {
+			println!("[sound] Failed to start sample loader: {error}");
+		}
+	}
+
+	pub fn play(&self, sound: Sound, params: PlayParams) {
+		self.send(ProviderCmd::Play(sound, params));
 	}
 
-	pub fn play(&self, sound: Sound) {
-		self.cmd_tx.send(ProviderCmd::Play(sound)).unwrap();
+	pub fn set_mix(&self, mix: MixerSettings) {
+		self.send(ProviderCmd::SetMix(mix));
+	}
+
+	pub fn set_music_state(&mut self, state: MusicState) {
+		// Intensity only needs to be roughly right, so avoid flooding the queue with tiny changes.
+		let intensity_changed = (state.intensity - self.music_state.intensity).abs() > 0.01;
+		if state.playing == self.music_state.playing && !intensity_changed {
+			return
+		}
+
+		// Only remember what actually reached the sequencer, so a dropped update is retried next frame.
+		if self.send(ProviderCmd::SetMusicState(state)) {
+			self.music_state = state;
+		}
+	}
+
+	pub fn stats(&self) -> SoundStats {
+		self.stats.get()
+	}
+
+	/// Fire and forget - never blocks, and never fails. Returns whether the command was queued.
+	fn send(&self, cmd: ProviderCmd) -> bool {
+		let Some(cmd_tx) = &self.cmd_tx else { return false };
+
+		let mut stats = self.stats.get();
+
+		match cmd_tx.try_send(cmd) {
+			Ok(()) => return true,
+			Err(TrySendError::Full(_)) => stats.dropped_queue_full += 1,
+			Err(TrySendError::Disconnected(_)) => stats.dropped_disconnected += 1,
+		}
+
+		self.stats.set(stats);
+		false
 	}
 }
 
 
+#[derive(Copy, Clone, Debug)]
 pub enum Sound {
 	Plik,
 	Bong,
 	Thup,
 	Unthup,
 	Tada,
+	Hmm,
+}
+
+impl Sound {
+	pub const ALL: [Sound; 6] = [Sound::Plik, Sound::Bong, Sound::Thup, Sound::Unthup, Sound::Tada, Sound::Hmm];
+
+	pub fn name(&self) -> &'static str {
+		match self {
+			Sound::Plik => "plik",
+			Sound::Bong => "bong",
+			Sound::Thup => "thup",
+			Sound::Unthup => "unthup",
+			Sound::Tada => "tada",
+			Sound::Hmm => "hmm",
+		}
+	}
+
+	pub fn category(&self) -> SoundCategory {
+		match self {
+			Sound::Bong | Sound::Tada => SoundCategory::Events,
+			_ => SoundCategory::Feedback,
+		}
+	}
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct PlayParams {
+	/// -1 is hard left, 1 is hard right.
+	pub pan: f32,
+
+	/// Playback rate. Like speeding up a tape, this also shortens the sound.
+	pub pitch: f32,
+
+	pub gain: f32,
+
+	/// Seconds to wait before starting.
+	pub delay: f32,
+}
+
+impl Default for PlayParams {
+	fn default() -> Self {
+		PlayParams {
+			pan: 0.0,
+			pitch: 1.0,
+			gain: 1.0,
+			delay: 0.0,
+		}
+	}
+}
+
+/// Converts an interval in semitones into a pitch multiplier.
+pub fn semitones(interval: f32) -> f32 {
+	2.0f32.powf(interval / 12.0)
+}
+
+
+/// Renders a patch from start to finish as interleaved stereo at full volume, before any mixing.
+/// Doesn't need an audio device, so it can be used for exporting and comparing sounds.
+pub fn render_sound(patch: &SynthPatch, sample_rate: u32) -> Vec<f32> {
+	let dt = (sample_rate as f64).recip();
+	let mut voice = SynthVoice::default();
+	let mut buffer = Vec::new();
+
+	while !voice.is_finished() {
+		let sample = voice.next_sample(patch, dt);
+		buffer.extend_from_slice(&[sample, sample]);
+	}
+
+	buffer
+}
+
+/// Renders `patch` and writes it to `path` as a 16 bit stereo WAV file.
+pub fn export_sound(patch: &SynthPatch, sample_rate: u32, path: &std::path::Path) -> anyhow::Result<()> {
+	let samples = render_sound(patch, sample_rate);
+	crate::wav::write_wav(path, sample_rate, 2, &samples)
 }
 
 
 
 enum ProviderCmd {
-	Play(Sound)
+	Play(Sound, PlayParams),
+	SetMix(MixerSettings),
+	SetPatches(Arc<PatchBank>),
+	SetSamples(Arc<SampleBank>),
+	SetMusicState(MusicState),
+}
+
+
+/// Something the audio thread has let go of, which may be the last reference.
+/// Never read - receiving it is only so it can be dropped.
+#[allow(dead_code)]
+enum Retired {
+	Patches(Arc<PatchBank>),
+	Samples(Arc<SampleBank>),
+	Sample(Arc<SampleData>),
 }
 
 
@@ -46,135 +314,210 @@ enum ProviderCmd {
 
 struct Provider {
 	cmd_rx: Receiver<ProviderCmd>,
+	retired_tx: SyncSender<Retired>,
 
+	/// Playing sounds, oldest first.
 	sounds: Vec<SoundState>,
+
+	/// Sounds waiting out their delay. These don't count towards the voice limit until they start,
+	/// so a burst of staggered notes can't steal voices from sounds that are already audible.
+	scheduled: Vec<SoundState>,
+	patches: Arc<PatchBank>,
+	samples: Arc<SampleBank>,
+	sequencer: Sequencer,
+
+	/// Shared with `SoundSystem` so it knows what rate to resample to.
+	device_sample_rate: Arc<AtomicU32>,
+
+	mix: MixerSettings,
+	limiter: Limiter,
 	dt: f64,
+	sample_rate: f64,
 }
 
 impl audio::Provider for Provider {
 	fn on_configuration_changed(&mut self, cfg: audio::Configuration) {
 		println!("[provider] Configuration changed {cfg:?}");
 		self.dt = (cfg.sample_rate as f64).recip();
+		self.sample_rate = cfg.sample_rate as f64;
+		self.limiter.set_sample_rate(cfg.sample_rate);
+		self.device_sample_rate.store(cfg.sample_rate, Ordering::Relaxed);
 	}
 
 	fn fill_buffer(&mut self, buffer: &mut [f32]) {
 		for cmd in self.cmd_rx.try_iter() {
 			match cmd {
-				ProviderCmd::Play(sound) => {
-					self.sounds.push(SoundState {
-						sound,
-						phase: 0.0,
-						env: 0.0,
-					});
+				ProviderCmd::Play(sound, params) => {
+					let sample = self.samples.get(sound).cloned();
+					let state = SoundState::new(sound, params, self.sample_rate, sample);
+
+					if state.delay_frames == 0 {
+						self.start_voice(state);
+					} else if self.scheduled.len() < MAX_VOICES {
+						self.scheduled.push(state);
+					} else {
+						self.retire_voice(state);
+					}
+				}
+
+				ProviderCmd::SetMix(mix) => self.mix = mix,
+				ProviderCmd::SetPatches(patches) => {
+					let old_patches = std::mem::replace(&mut self.patches, patches);
+					self.retire(Retired::Patches(old_patches));
+				}
+
+				ProviderCmd::SetSamples(samples) => {
+					let old_samples = std::mem::replace(&mut self.samples, samples);
+					self.retire(Retired::Samples(old_samples));
 				}
+
+				ProviderCmd::SetMusicState(state) => self.sequencer.set_state(state),
+			}
+		}
+
+		// Anything due to start during this buffer becomes a voice now, and skips the rest of its delay in `fill`.
+		let buffer_frames = buffer.len() / 2;
+		let mut index = 0;
+		while index < self.scheduled.len() {
+			if self.scheduled[index].delay_frames < buffer_frames {
+				let state = self.scheduled.remove(index);
+				self.start_voice(state);
+			} else {
+				self.scheduled[index].delay_frames -= buffer_frames;
+				index += 1;
 			}
 		}
 
 		buffer.fill(0.0);
 
+		let master_gain = self.mix.master.gain();
+
 		for sound in self.sounds.iter_mut() {
-			sound.fill(buffer, self.dt);
+			let gain = master_gain * self.mix.category(sound.sound.category()).gain();
+			sound.fill(self.patches.get(sound.sound), buffer, self.dt, gain);
 		}
 
-		self.sounds.retain(|s| !s.is_finished());
-	}
-}
+		if self.mix.music_enabled {
+			let effects_playing = !self.sounds.is_empty();
+			let gain = master_gain * self.mix.music.gain();
+			self.sequencer.fill(buffer, self.dt, gain, effects_playing);
+		}
 
+		let mut index = 0;
+		while index < self.sounds.len() {
+			if self.sounds[index].is_finished() {
+				let state = self.sounds.remove(index);
+				self.retire_voice(state);
+			} else {
+				index += 1;
+			}
+		}
 
-struct SoundState {
-	sound: Sound,
-	phase: f64,
-	env: f64,
+		self.limiter.process(buffer);
+	}
 }
 
-impl SoundState {
-	fn fill(&mut self, buffer: &mut [f32], dt: f64) {
-		for [l, r] in buffer.array_chunks_mut() {
-			let sample = match self.sound {
-				Sound::Plik => generate_plik_sample(&mut self.phase, &mut self.env, dt),
-				Sound::Bong => generate_bong_sample(&mut self.phase, &mut self.env, dt),
-				Sound::Thup => generate_thup_sample(&mut self.phase, &mut self.env, dt),
-				Sound::Unthup => generate_unthup_sample(&mut self.phase, &mut self.env, dt),
-				Sound::Tada => generate_tada_sample(&mut self.phase, &mut self.env, dt),
-			};
+impl Provider {
+	/// Steals the oldest voices if there are too many playing.
+	fn start_voice(&mut self, state: SoundState) {
+		let max_voices = self.mix.max_voices.clamp(1, MAX_VOICES);
+		while self.sounds.len() >= max_voices {
+			let stolen = self.sounds.remove(0);
+			self.retire_voice(stolen);
+		}
 
-			let sample = sample * 0.3;
+		self.sounds.push(state);
+	}
 
-			*l += sample;
-			*r += sample;
+	/// Hands a voice's sample back to the game thread if this was the last voice using it,
+	/// as happens once the bank it came from has been replaced.
+	fn retire_voice(&self, state: SoundState) {
+		if let SoundSource::Sample { data, .. } = state.source {
+			if Arc::strong_count(&data) == 1 {
+				self.retire(Retired::Sample(data));
+			}
 		}
 	}
 
-	fn is_finished(&self) -> bool {
-		self.env > 1.0
+	/// Freeing memory can block, so it's left to the game thread. If the queue is somehow full,
+	/// it gets dropped here anyway rather than waiting.
+	fn retire(&self, retired: Retired) {
+		let _ = self.retired_tx.try_send(retired);
 	}
 }
 
 
-use std::f64::consts::TAU;
-
-fn generate_plik_sample(phase: &mut f64, env_phase: &mut f64, dt: f64) -> f32 {
-	let env = gen_env(*env_phase);
-	let osc = (*phase * TAU).sin() as f32;
-
-	*phase = (*phase + dt * 600.0 * (1.0 - env_phase.powi(2)*0.7)).fract();
-	*env_phase += dt * 12.0;
+struct SoundState {
+	sound: Sound,
+	source: SoundSource,
 
-	osc * env * 0.8
+	pitch: f64,
+	gain_left: f32,
+	gain_right: f32,
+	delay_frames: usize,
 }
 
-fn generate_bong_sample(phase: &mut f64, env_phase: &mut f64, dt: f64) -> f32 {
-	let env = gen_env(*env_phase);
-	let osc = (*phase * TAU * 1.0).sin() as f32;
-	let osc2 = (*phase * TAU * (2.0 - *env_phase*0.3)).sin() as f32;
-
-	*phase += dt * (120.0 + (*env_phase * 36.0).sin() * 5.0 - *env_phase*40.0);
-	*env_phase += dt / 2.2;
+enum SoundSource {
+	Synth(SynthVoice),
 
-	(osc + osc2) * env * 0.6
+	/// Position is in frames, and fractional so pitch can be changed.
+	Sample {
+		data: Arc<SampleData>,
+		position: f64,
+	},
 }
 
-fn generate_thup_sample(phase: &mut f64, env_phase: &mut f64, dt: f64) -> f32 {
-	let env = gen_env(*env_phase);
-	let osc = (*phase * TAU).sin() as f32;
-
-	*phase = (*phase + dt * 210.0 * 2.0 / 3.0 * (1.0 - env_phase.powi(2) * 0.6)).fract();
-	*env_phase += dt / 0.05;
+impl SoundState {
+	fn new(sound: Sound, params: PlayParams, sample_rate: f64, sample: Option<Arc<SampleData>>) -> SoundState {
+		let source = match sample {
+			Some(data) => SoundSource::Sample { data, position: 0.0 },
+			None => SoundSource::Synth(SynthVoice::default()),
+		};
 
-	osc * env
-}
+		// Equal power panning, normalised so centered sounds are as loud as they always were.
+		let pan_angle = (params.pan.clamp(-1.0, 1.0) + 1.0) * std::f32::consts::FRAC_PI_4;
+		let pan_gain = params.gain * std::f32::consts::SQRT_2;
 
-fn generate_unthup_sample(phase: &mut f64, env_phase: &mut f64, dt: f64) -> f32 {
-	let env = gen_env(*env_phase);
-	let osc = (*phase * TAU).sin() as f32;
+		SoundState {
+			sound,
+			source,
 
-	*phase = (*phase + dt * 210.0 * (1.0 + env_phase.powi(2) * 0.9)).fract();
-	*env_phase += dt / 0.1;
+			pitch: params.pitch.max(0.01) as f64,
+			gain_left: pan_angle.cos() * pan_gain,
+			gain_right: pan_angle.sin() * pan_gain,
+			delay_frames: (params.delay.max(0.0) as f64 * sample_rate) as usize,
+		}
+	}
 
-	osc * env
-}
+	fn fill(&mut self, patch: &SynthPatch, buffer: &mut [f32], dt: f64, gain: f32) {
+		let skip_frames = self.delay_frames.min(buffer.len() / 2);
+		self.delay_frames -= skip_frames;
 
-fn generate_tada_sample(phase: &mut f64, env_phase: &mut f64, dt: f64) -> f32 {
-	let env = gen_env(*env_phase);
-	let env2 = gen_env(*env_phase - 0.1);
-	let osc = (*phase * TAU).sin() as f32;
-	let osc2 = (*phase * TAU * 3.0 / 2.0).sin() as f32;
+		let dt = dt * self.pitch;
 
-	*phase = (*phase + dt * 800.0) % 6.0;
-	*env_phase += dt / 1.0;
+		for [l, r] in buffer[skip_frames*2..].array_chunks_mut() {
+			let [left, right] = match &mut self.source {
+				SoundSource::Synth(voice) => {
+					let sample = voice.next_sample(patch, dt);
+					[sample, sample]
+				}
 
-	(osc * env + osc2 * env2) / 2.0
-}
+				SoundSource::Sample { data, position } => {
+					let frame = data.frame_at(*position);
+					*position += dt * data.sample_rate as f64;
+					frame
+				}
+			};
 
-fn gen_env(phase: f64) -> f32 {
-	if phase < 0.0 || phase > 1.0 {
-		return 0.0
+			*l += left * gain * self.gain_left;
+			*r += right * gain * self.gain_right;
+		}
 	}
 
-	if phase < 0.02 {
-		1.0 - (1.0 - phase/0.02).powi(2).clamp(0.0, 1.0) as f32
-
-	} else {
-		(1.0 - phase/0.98).powi(3).clamp(0.0, 1.0) as f32
+	fn is_finished(&self) -> bool {
+		match &self.source {
+			SoundSource::Synth(voice) => voice.is_finished(),
+			SoundSource::Sample { data, position } => *position >= data.num_frames() as f64,
+		}
 	}
-}
\ No newline at end of file
+}
@@ -0,0 +1,159 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::board::{Board, BoardSnapshot, CellState, GameStatus};
+use crate::net::{Connection, Listener};
+use crate::view::CellResponse;
+
+use std::collections::VecDeque;
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum SpectateMessage {
+	/// The whole board, including its seed. Sent to spectators as they connect, and whenever the board changes
+	/// other than by an action, like when a new game starts.
+	Snapshot(BoardSnapshot),
+
+	/// Something the player did. Applying it to the same board reproduces the change exactly, as with replays.
+	Action { position: [i32; 2], response: CellResponse },
+}
+
+type SpectateConnection = Connection<SpectateMessage, SpectateMessage>;
+
+
+/// Streams the local game to any spectators that connect. Spectators never send anything back.
+pub struct Broadcaster {
+	listener: Listener,
+	spectators: Vec<SpectateConnection>,
+
+	/// Actions since the last update, sent in order.
+	pending_actions: Vec<SpectateMessage>,
+
+	/// What spectators have been sent, so changes that didn't come from actions can be caught.
+	published_generation: Option<u32>,
+	published_states: Vec<CellState>,
+}
+
+impl Broadcaster {
+	/// Only listens on the loopback interface, since streams are meant for another screen on the same machine.
+	pub fn bind(port: u16) -> anyhow::Result<Broadcaster> {
+		let listener = Listener::bind(("127.0.0.1", port))?;
+
+		if let Some(address) = listener.local_addr() {
+			println!("[spectate] Broadcasting on {address}");
+		}
+
+		Ok(Broadcaster {
+			listener,
+			spectators: Vec::new(),
+			pending_actions: Vec::new(),
+			published_generation: None,
+			published_states: Vec::new(),
+		})
+	}
+
+	pub fn num_spectators(&self) -> usize {
+		self.spectators.len()
+	}
+
+	/// Queues an action the player just made, to be sent on the next update.
+	pub fn publish_action(&mut self, position: Vec2i, response: CellResponse) {
+		self.pending_actions.push(SpectateMessage::Action {
+			position: [position.x, position.y],
+			response,
+		});
+	}
+
+	/// Sends everything that's happened since the last update, and catches up new spectators.
+	/// `generation` should change whenever a new game starts.
+	pub fn update(&mut self, board: &Board, status: GameStatus, generation: u32) {
+		self.spectators.retain(|spectator| {
+			// Nothing is expected from spectators, but drain anything they send so it doesn't pile up.
+			spectator.recv_all();
+
+			let connected = spectator.is_connected();
+			if !connected {
+				println!("[spectate] {} stopped watching", spectator.peer());
+			}
+
+			connected
+		});
+
+		let pending_actions = std::mem::take(&mut self.pending_actions);
+		let states_changed = !board.states.iter().eq(self.published_states.iter());
+
+		// Anything that changed the board without an action, like a new game, versus claims or co-op updates,
+		// can't be reproduced by spectators so they're sent the whole board instead.
+		if self.published_generation != Some(generation) || (states_changed && pending_actions.is_empty()) {
+			let snapshot = SpectateMessage::Snapshot(BoardSnapshot::new(board, status));
+			self.broadcast(&snapshot);
+		} else {
+			for action in pending_actions.iter() {
+				self.broadcast(action);
+			}
+		}
+
+		while let Some(spectator) = self.listener.accept::<SpectateMessage, SpectateMessage>() {
+			println!("[spectate] {} is watching", spectator.peer());
+			spectator.send(SpectateMessage::Snapshot(BoardSnapshot::new(board, status)));
+			self.spectators.push(spectator);
+		}
+
+		self.published_generation = Some(generation);
+		self.published_states = board.states.iter().copied().collect();
+	}
+
+	fn broadcast(&self, message: &SpectateMessage) {
+		for spectator in self.spectators.iter() {
+			spectator.send(message.clone());
+		}
+	}
+}
+
+
+/// Watches a game streamed by a `Broadcaster`, optionally running behind it by a fixed delay.
+pub struct Spectator {
+	connection: SpectateConnection,
+
+	/// Seconds between a message arriving and it being shown.
+	pub delay: f32,
+
+	/// Messages waiting out the delay, with the time they arrived.
+	buffered: VecDeque<(f32, SpectateMessage)>,
+}
+
+impl Spectator {
+	pub fn connect(address: &str, delay: f32) -> anyhow::Result<Spectator> {
+		let connection = SpectateConnection::connect(address)?;
+
+		println!("[spectate] Watching {}", connection.peer());
+
+		Ok(Spectator {
+			connection,
+			delay: delay.max(0.0),
+			buffered: VecDeque::new(),
+		})
+	}
+
+	pub fn peer(&self) -> std::net::SocketAddr {
+		self.connection.peer()
+	}
+
+	/// True once the stream has ended and everything buffered has been shown.
+	pub fn is_finished(&self) -> bool {
+		!self.connection.is_connected() && self.buffered.is_empty()
+	}
+
+	/// Returns messages that are due to be shown at `time`, in the order they were sent.
+	pub fn update(&mut self, time: f32) -> Vec<SpectateMessage> {
+		self.buffered.extend(self.connection.recv_all().into_iter().map(|message| (time, message)));
+
+		let mut due = Vec::new();
+		while self.buffered.front().is_some_and(|(received_at, _)| time >= received_at + self.delay) {
+			due.extend(self.buffered.pop_front().map(|(_, message)| message));
+		}
+
+		due
+	}
+}
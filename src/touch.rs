@@ -0,0 +1,171 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use std::collections::HashMap;
+use std::time::Instant;
+
+
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq)]
+#[serde(default)]
+pub struct TouchSettings {
+	/// How long a touch must be held to count as a long press, in seconds.
+	pub long_press_time: f32,
+
+	/// How far a touch can move, in points, before it's treated as a drag rather than a tap.
+	pub tap_slop: f32,
+}
+
+impl Default for TouchSettings {
+	fn default() -> Self {
+		TouchSettings {
+			long_press_time: 0.4,
+			tap_slop: 12.0,
+		}
+	}
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub enum TouchGesture {
+	Tap(egui::Pos2),
+	LongPress(egui::Pos2),
+}
+
+
+struct Touch {
+	start_pos: egui::Pos2,
+	pos: egui::Pos2,
+	start_time: Instant,
+
+	moved: bool,
+	long_pressed: bool,
+}
+
+
+#[derive(Default)]
+pub struct TouchInput {
+	touches: HashMap<egui::TouchId, Touch>,
+
+	/// Whether more than one finger has been down since all fingers were last lifted.
+	/// Multi-touch gestures are for panning and zooming, so never produce taps.
+	is_multi_touch: bool,
+
+	/// Swaps taps and long presses, so flags can be placed with a tap.
+	pub flag_mode: bool,
+
+	/// Touch controls are only shown once a touch screen has actually been used.
+	has_touched: bool,
+}
+
+impl TouchInput {
+	pub fn update(&mut self, egui: &egui::Context, settings: &TouchSettings) -> Vec<TouchGesture> {
+		let mut gestures = Vec::new();
+
+		let events = egui.input(|input| input.events.clone());
+
+		for event in events {
+			let egui::Event::Touch{id, phase, pos, ..} = event else { continue };
+
+			self.has_touched = true;
+
+			match phase {
+				egui::TouchPhase::Start => {
+					self.touches.insert(id, Touch {
+						start_pos: pos,
+						pos,
+						start_time: Instant::now(),
+
+						moved: false,
+						long_pressed: false,
+					});
+
+					if self.touches.len() > 1 {
+						self.is_multi_touch = true;
+					}
+				}
+
+				egui::TouchPhase::Move => if let Some(touch) = self.touches.get_mut(&id) {
+					touch.pos = pos;
+					touch.moved |= touch.start_pos.distance(pos) > settings.tap_slop;
+				}
+
+				egui::TouchPhase::End => if let Some(touch) = self.touches.remove(&id) {
+					if !self.is_multi_touch && !touch.moved && !touch.long_pressed {
+						gestures.push(TouchGesture::Tap(touch.pos));
+					}
+				}
+
+				egui::TouchPhase::Cancel => {
+					self.touches.remove(&id);
+				}
+			}
+		}
+
+		if self.touches.is_empty() {
+			self.is_multi_touch = false;
+		}
+
+		if let Some(touch) = self.single_held_touch() {
+			if touch.start_time.elapsed().as_secs_f32() >= settings.long_press_time {
+				touch.long_pressed = true;
+				gestures.push(TouchGesture::LongPress(touch.pos));
+			}
+		}
+
+		gestures
+	}
+
+	/// The only touch currently down, if it could still become a long press.
+	fn single_held_touch(&mut self) -> Option<&mut Touch> {
+		if self.is_multi_touch {
+			return None
+		}
+
+		self.touches.values_mut()
+			.next()
+			.filter(|touch| !touch.moved && !touch.long_pressed)
+	}
+
+	/// Draws the long press progress indicator and the flag mode toggle.
+	pub fn draw_ui(&mut self, egui: &egui::Context, settings: &TouchSettings) {
+		if let Some(touch) = self.single_held_touch() {
+			let progress = touch.start_time.elapsed().as_secs_f32() / settings.long_press_time;
+			let center = touch.pos;
+
+			// Hold off briefly so quick taps don't flash the indicator.
+			if progress > 0.15 {
+				let painter = egui.layer_painter(egui::LayerId::new(egui::Order::Foreground, egui::Id::new("long_press")));
+				let radius = 36.0;
+				let num_segments = 32;
+
+				let points = (0..=num_segments)
+					.map(|idx| idx as f32 / num_segments as f32 * progress.min(1.0) * std::f32::consts::TAU)
+					.map(|angle| center + radius * egui::vec2(angle.sin(), -angle.cos()))
+					.collect();
+
+				painter.circle_stroke(center, radius, egui::Stroke::new(6.0, egui::Color32::from_black_alpha(100)));
+				painter.add(egui::Shape::line(points, egui::Stroke::new(6.0, egui::Color32::WHITE)));
+			}
+		}
+
+		if !self.has_touched {
+			return
+		}
+
+		egui::Area::new("flag_mode_toggle")
+			.anchor(egui::Align2::CENTER_BOTTOM, [0.0, -16.0])
+			.show(egui, |ui| {
+				let label = match self.flag_mode {
+					false => "Tap to open",
+					true => "Tap to flag",
+				};
+
+				let button = egui::Button::new(egui::RichText::new(label).size(24.0))
+					.min_size(egui::vec2(200.0, 56.0));
+
+				if ui.add(button).clicked() {
+					self.flag_mode = !self.flag_mode;
+				}
+			});
+	}
+}
@@ -0,0 +1,363 @@
+use toybox::prelude::*;
+use serde::{Serialize, Deserialize};
+
+use crate::board::{Board, MAX_BOARD_SIZE};
+use crate::net::{Connection, Listener};
+
+
+/// Both players play on the same board, seeded by the host or the hot-seat game.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone)]
+pub struct VersusStart {
+	pub seed: u64,
+	pub width: i32,
+	pub height: i32,
+	pub mines: usize,
+
+	pub first_player: usize,
+
+	/// Counts up with each rematch, so moves meant for an earlier board can be told apart.
+	pub match_number: u32,
+}
+
+impl VersusStart {
+	/// Whether a board can be made from this. Starts come from the host over the network, so are checked first.
+	pub fn is_valid(&self) -> bool {
+		let valid_size = |size| (1..=MAX_BOARD_SIZE).contains(&size);
+		valid_size(self.width) && valid_size(self.height)
+			&& self.mines <= (self.width * self.height) as usize
+	}
+
+	pub fn make_board(&self) -> Board {
+		Board::with_bombs(Vec2i::new(self.width, self.height), self.mines, self.seed)
+	}
+}
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VersusOutcome {
+	Winner(usize),
+	Draw,
+}
+
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum VersusMessage {
+	/// Guest to host, on connecting.
+	Hello { name: String },
+
+	/// Host to guest, in response to `Hello`.
+	Welcome { name: String },
+
+	/// Host to guest.
+	Start(VersusStart),
+
+	/// Either player, when they open a cell on their turn.
+	/// The game is deterministic, so both sides apply the same moves to identical boards.
+	Open { match_number: u32, position: [i32; 2] },
+}
+
+type VersusConnection = Connection<VersusMessage, VersusMessage>;
+
+
+/// Something the game needs to react to.
+pub enum VersusEvent {
+	/// Host only. An opponent has connected, so a match can start.
+	Joined,
+
+	/// A match is starting, or the host has started a rematch.
+	Start(VersusStart),
+
+	/// The opponent opened a cell.
+	Open(Vec2i),
+}
+
+
+enum Link {
+	/// Both players share one machine and take turns with the same input.
+	HotSeat,
+
+	Host {
+		listener: Listener,
+		guest: Option<VersusConnection>,
+	},
+
+	Guest {
+		connection: VersusConnection,
+	},
+}
+
+
+/// A game of Minesweeper Flags. Players take turns opening cells, trying to find mines rather than avoid them.
+/// Opening a mine claims it and gives another turn, and opening a safe cell passes the turn to the other player.
+/// Whoever claims more than half of the mines wins.
+pub struct VersusSession {
+	link: Link,
+
+	/// Indexed by player. The host is always player 0.
+	pub names: [String; 2],
+	pub scores: [usize; 2],
+	pub turn: usize,
+
+	/// Which player claimed each mine found so far.
+	pub claims: Vec<(Vec2i, usize)>,
+
+	match_number: u32,
+	pending_events: Vec<VersusEvent>,
+}
+
+impl VersusSession {
+	pub const HOST_PLAYER: usize = 0;
+	pub const GUEST_PLAYER: usize = 1;
+
+	pub fn hot_seat(names: [String; 2]) -> VersusSession {
+		VersusSession::new(Link::HotSeat, names)
+	}
+
+	pub fn host(port: u16, name: String) -> anyhow::Result<VersusSession> {
+		let listener = Listener::bind(("0.0.0.0", port))?;
+
+		if let Some(address) = listener.local_addr() {
+			println!("[versus] Hosting on {address}");
+		}
+
+		Ok(VersusSession::new(Link::Host { listener, guest: None }, [name, String::new()]))
+	}
+
+	pub fn join(address: &str, name: String) -> anyhow::Result<VersusSession> {
+		let connection = VersusConnection::connect(address)?;
+		connection.send(VersusMessage::Hello { name: name.clone() });
+
+		println!("[versus] Joined {}", connection.peer());
+
+		Ok(VersusSession::new(Link::Guest { connection }, [String::new(), name]))
+	}
+
+	fn new(link: Link, names: [String; 2]) -> VersusSession {
+		VersusSession {
+			link,
+			names,
+			scores: [0; 2],
+			turn: 0,
+			claims: Vec::new(),
+			match_number: 0,
+			pending_events: Vec::new(),
+		}
+	}
+
+	/// The largest odd number of mines, up to `requested`, that leaves at least one safe cell. Mines always land
+	/// in distinct cells, so with an odd number someone always ends up with a majority.
+	pub fn mine_count(board_size: Vec2i, requested: usize) -> usize {
+		let num_cells = (board_size.x * board_size.y).max(0) as usize;
+		let mines = requested.min(num_cells.saturating_sub(1));
+
+		match mines % 2 {
+			0 => mines.saturating_sub(1).max(1),
+			_ => mines,
+		}
+	}
+
+	pub fn is_hot_seat(&self) -> bool {
+		matches!(self.link, Link::HotSeat)
+	}
+
+	/// Whether this instance decides when matches start. Guests just follow along.
+	pub fn can_start_match(&self) -> bool {
+		match &self.link {
+			Link::HotSeat => true,
+			Link::Host { guest, .. } => guest.is_some(),
+			Link::Guest{..} => false,
+		}
+	}
+
+	/// False once a guest has lost its connection to the host.
+	pub fn is_connected(&self) -> bool {
+		match &self.link {
+			Link::Guest { connection } => connection.is_connected(),
+			_ => true,
+		}
+	}
+
+	pub fn has_opponent(&self) -> bool {
+		match &self.link {
+			Link::HotSeat => true,
+			Link::Host { guest, .. } => guest.is_some(),
+			Link::Guest { connection } => connection.is_connected(),
+		}
+	}
+
+	/// The player at this machine, or None if both are.
+	pub fn local_player(&self) -> Option<usize> {
+		match self.link {
+			Link::HotSeat => None,
+			Link::Host{..} => Some(Self::HOST_PLAYER),
+			Link::Guest{..} => Some(Self::GUEST_PLAYER),
+		}
+	}
+
+	pub fn is_local_turn(&self) -> bool {
+		self.local_player().map_or(true, |player| player == self.turn)
+	}
+
+	/// Host and hot-seat only. Starts a new match, with players taking turns to go first.
+	pub fn start_match(&mut self, board_size: Vec2i, mines: usize) {
+		if !self.can_start_match() {
+			return
+		}
+
+		let start = VersusStart {
+			seed: rand::random(),
+			width: board_size.x,
+			height: board_size.y,
+			mines,
+			first_player: self.match_number as usize % 2,
+			match_number: self.match_number + 1,
+		};
+
+		if let Link::Host { guest: Some(guest), .. } = &self.link {
+			guest.send(VersusMessage::Start(start));
+		}
+
+		self.pending_events.push(VersusEvent::Start(start));
+	}
+
+	/// Handles incoming messages and connections. Should be called once a frame.
+	pub fn update(&mut self) -> Vec<VersusEvent> {
+		let mut events = std::mem::take(&mut self.pending_events);
+		let is_local_turn = self.is_local_turn();
+
+		let connection = match &mut self.link {
+			Link::HotSeat => None,
+
+			Link::Host { listener, guest } => {
+				if guest.as_ref().is_some_and(|guest| !guest.is_connected()) {
+					println!("[versus] '{}' disconnected", self.names[Self::GUEST_PLAYER]);
+					*guest = None;
+				}
+
+				while let Some(connection) = listener.accept::<VersusMessage, VersusMessage>() {
+					// Only two can play. Anyone else is dropped, which closes their connection.
+					if guest.is_none() {
+						println!("[versus] {} connected", connection.peer());
+						connection.send(VersusMessage::Welcome { name: self.names[Self::HOST_PLAYER].clone() });
+						*guest = Some(connection);
+						events.push(VersusEvent::Joined);
+					} else {
+						println!("[versus] Turning away {}, already have an opponent", connection.peer());
+					}
+				}
+
+				guest.as_ref()
+			}
+
+			Link::Guest { connection } => Some(&*connection),
+		};
+
+		let Some(connection) = connection else { return events };
+
+		for message in connection.recv_all() {
+			match message {
+				VersusMessage::Hello { name } => self.names[Self::GUEST_PLAYER] = name,
+				VersusMessage::Welcome { name } => self.names[Self::HOST_PLAYER] = name,
+				VersusMessage::Start(start) if start.is_valid() => events.push(VersusEvent::Start(start)),
+				VersusMessage::Start(start) => println!("[versus] Ignoring invalid match start {start:?}"),
+
+				// Moves are only accepted on the opponent's turn, and for the current match.
+				VersusMessage::Open { match_number, position } if match_number == self.match_number && !is_local_turn => {
+					events.push(VersusEvent::Open(Vec2i::new(position[0], position[1])));
+				}
+
+				VersusMessage::Open{..} => println!("[versus] Ignoring move out of turn"),
+			}
+		}
+
+		events
+	}
+
+	/// Resets scores for a match that's starting.
+	pub fn begin(&mut self, start: &VersusStart) {
+		self.match_number = start.match_number;
+		self.turn = start.first_player % 2;
+		self.scores = [0; 2];
+		self.claims.clear();
+	}
+
+	/// Tells the opponent about a cell the local player just opened.
+	pub fn send_open(&self, position: Vec2i) {
+		let message = VersusMessage::Open {
+			match_number: self.match_number,
+			position: [position.x, position.y],
+		};
+
+		match &self.link {
+			Link::HotSeat | Link::Host { guest: None, .. } => {}
+			Link::Host { guest: Some(connection), .. } | Link::Guest { connection } => connection.send(message),
+		}
+	}
+
+	/// The current player found a mine, and goes again.
+	pub fn claim(&mut self, position: Vec2i) {
+		self.scores[self.turn] += 1;
+		self.claims.push((position, self.turn));
+	}
+
+	/// The current player opened a safe cell, so it's the other player's turn.
+	pub fn pass_turn(&mut self) {
+		self.turn = 1 - self.turn;
+	}
+
+	/// The result once one player has more than half the mines, or they've all been found.
+	pub fn outcome(&self, num_mines: usize) -> Option<VersusOutcome> {
+		let [first, second] = self.scores;
+
+		if let Some(winner) = self.scores.iter().position(|&score| score * 2 > num_mines) {
+			return Some(VersusOutcome::Winner(winner))
+		}
+
+		if first + second < num_mines {
+			return None
+		}
+
+		// Only possible with an even number of mines, since with an odd number someone always has a majority.
+		Some(VersusOutcome::Draw)
+	}
+}
+
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn session_with_scores(scores: [usize; 2]) -> VersusSession {
+		let mut session = VersusSession::hot_seat(["a".to_owned(), "b".to_owned()]);
+		session.scores = scores;
+		session
+	}
+
+	#[test]
+	fn mine_count_is_odd_and_leaves_a_safe_cell() {
+		assert_eq!(VersusSession::mine_count(Vec2i::new(3, 3), 8), 7);
+		assert_eq!(VersusSession::mine_count(Vec2i::new(3, 3), 100), 7);
+		assert_eq!(VersusSession::mine_count(Vec2i::new(5, 5), 100), 23);
+		assert_eq!(VersusSession::mine_count(Vec2i::new(5, 5), 10), 9);
+		assert_eq!(VersusSession::mine_count(Vec2i::new(5, 5), 11), 11);
+	}
+
+	#[test]
+	fn majority_wins() {
+		assert_eq!(session_with_scores([3, 1]).outcome(5), Some(VersusOutcome::Winner(0)));
+		assert_eq!(session_with_scores([0, 3]).outcome(5), Some(VersusOutcome::Winner(1)));
+	}
+
+	#[test]
+	fn even_split_is_a_draw() {
+		assert_eq!(session_with_scores([2, 2]).outcome(4), Some(VersusOutcome::Draw));
+	}
+
+	#[test]
+	fn undecided_while_mines_remain() {
+		assert_eq!(session_with_scores([2, 1]).outcome(5), None);
+		assert_eq!(session_with_scores([2, 1]).outcome(4), None);
+		assert_eq!(session_with_scores([0, 0]).outcome(5), None);
+	}
+}
@@ -3,44 +3,228 @@ use toybox::prelude::*;
 use crate::board::*;
 use crate::ext::*;
 use crate::map::*;
-use crate::quad_builder::QuadBuilder;
+use crate::quad_builder::{QuadBuilder, QuadBuffer};
+use crate::skin::{Skin, SkinGlyphs, AtlasUniforms};
+use crate::palette::{Palette, PalettePreset, player_color};
 
+use serde::{Serialize, Deserialize};
+use std::time::{Duration, Instant};
 
 
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Serialize, Deserialize)]
 pub enum CellResponse {
 	BombHit,
 	FlagPlaced,
 	FlagRemoved,
 	OpenSpaceUncovered,
 	UnsafeSpaceUncovered,
+	Chord,
+	QuestionPlaced,
+	QuestionRemoved,
+}
+
+
+pub fn open_response(board: &Board, position: Vec2i) -> Option<CellResponse> {
+	if !board.states.get(position)?.is_openable() {
+		return None
+	}
+
+	let response = match *board.types.get(position)? {
+		CellType::Bomb => CellResponse::BombHit,
+		CellType::Empty => CellResponse::OpenSpaceUncovered,
+		CellType::BombAdjacent(_) => CellResponse::UnsafeSpaceUncovered,
+	};
+
+	Some(response)
+}
+
+/// Cycles a cell through flagged, and optionally questioned, back to unopened.
+pub fn flag_response(board: &Board, position: Vec2i, question_marks: bool) -> Option<CellResponse> {
+	match *board.states.get(position)? {
+		CellState::Unopened => Some(CellResponse::FlagPlaced),
+		CellState::Flagged if question_marks => Some(CellResponse::QuestionPlaced),
+		CellState::Flagged => Some(CellResponse::FlagRemoved),
+		CellState::Questioned => Some(CellResponse::QuestionRemoved),
+		CellState::Opened => None,
+	}
+}
+
+pub fn chord_response(board: &Board, position: Vec2i) -> Option<CellResponse> {
+	board.can_chord(position).then_some(CellResponse::Chord)
+}
+
+
+/// Which input device last interacted with the board, and so drives the highlight.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum InputDevice {
+	Mouse,
+	Keyboard,
+	Gamepad,
+	Touch,
+}
+
+
+/// Cursor movement and actions from a non-pointer device.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct CursorInput {
+	pub movement: Vec2i,
+	pub jump_to_unopened: bool,
+	pub open: bool,
+	pub flag: bool,
+	pub chord: bool,
+}
+
+impl CursorInput {
+	fn is_empty(&self) -> bool {
+		self.movement == Vec2i::zero() && !self.jump_to_unopened
+			&& !self.open && !self.flag && !self.chord
+	}
+}
+
+
+/// How the gaps between cells should be treated when picking.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum GapMode {
+	/// Picks the nearest cell.
+	Snap,
+	/// Picks nothing.
+	Empty,
+}
+
+
+#[derive(Debug, Copy, Clone)]
+pub struct CellPick {
+	pub position: Vec2i,
+
+	/// Position within the visible cell, from (0, 0) at its min corner to (1, 1) at its max.
+	pub sub_position: Vec2,
+}
+
+
+/// Fraction of each grid section covered by a visible cell.
+const CELL_SCALE: f32 = 0.95;
+
+/// Number of quad slots reserved for each cell in the quad buffer.
+const QUADS_PER_CELL: usize = 5;
+
+
+/// Delay between each step of a flood fill reveal.
+pub const REVEAL_STAGGER: f32 = 0.02;
+const REVEAL_DURATION: f32 = 0.15;
+
+/// Delay per cell of distance from the detonated mine when revealing the board on loss.
+const RIPPLE_STAGGER: f32 = 0.06;
+
+const FLAG_DROP_DURATION: f32 = 0.35;
+
+
+/// Everything that affects how a cell is drawn. Cells are only rebuilt when this changes.
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct CellVisual {
+	cell_type: CellType,
+	cell_state: CellState,
+	is_hovered: bool,
+	is_cursor: bool,
+	is_detonated: bool,
+	is_wrong_flag: bool,
+
+	/// Player index of another player whose cursor is over this cell, in multiplayer.
+	remote_cursor: Option<usize>,
+
+	/// Player index of whoever claimed this flag, in versus mode.
+	flag_owner: Option<usize>,
+	animation: Option<(AnimationKind, f32)>,
+}
+
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum AnimationKind {
+	Reveal,
+	FlagDrop,
+}
+
+#[derive(Debug, Copy, Clone)]
+struct CellAnimation {
+	kind: AnimationKind,
+	start_time: f32,
+	duration: f32,
+}
+
+impl CellAnimation {
+	/// Progress through the animation from 0 to 1, or None if it has finished.
+	fn progress(&self, time: f32) -> Option<f32> {
+		let progress = (time - self.start_time) / self.duration;
+		(progress < 1.0).then(|| progress.max(0.0))
+	}
+}
+
+
+/// Everything needed to draw quads from the current skin's atlas with the main shader.
+#[derive(Copy, Clone)]
+pub struct AtlasBinding {
+	pub image: gfx::ImageName,
+	pub sampler: gfx::SamplerName,
+	pub uniforms: AtlasUniforms,
+	pub blank_glyph: u16,
+}
+
+
+#[derive(Debug, Default, Copy, Clone)]
+pub struct DrawStats {
+	pub cells_rewritten: usize,
+	pub quads_uploaded: usize,
+	pub build_time: Duration,
 }
 
 
 pub struct BoardView {
 	main_vs: gfx::ShaderHandle,
 	main_fs: gfx::ShaderHandle,
-	atlas: gfx::ImageHandle,
 	sampler: gfx::SamplerName,
 
+	pub skin: Skin,
+	palette_preset: PalettePreset,
+
 	pub bounds: Aabb2,
 	pub hovered_cell: Option<Vec2i>,
+	pub hovered_sub_position: Option<Vec2>,
+	pub cursor: Option<Vec2i>,
+	pub active_device: InputDevice,
+	last_mouse_pos: Option<Vec2>,
+	pub gap_mode: GapMode,
+	pub animations_enabled: bool,
+	pub question_marks_enabled: bool,
+
+	/// Other players' cursors in multiplayer, with the player index used to pick their colour.
+	pub remote_cursors: Vec<(Vec2i, usize)>,
+
+	/// Mines claimed in versus mode, with the player index used to colour their flags.
+	pub flag_owners: Vec<(Vec2i, usize)>,
 
+	pub draw_stats: DrawStats,
+
+	board_size: Vec2i,
 	cell_bounds: Map<Aabb2>,
+
+	quad_buffer: QuadBuffer,
+	cell_visuals: Map<Option<CellVisual>>,
+	cell_animations: Map<Option<CellAnimation>>,
+	scratch_builder: QuadBuilder,
 }
 
 
 impl BoardView {
-	pub fn new(ctx: &mut toybox::Context, board_size: Vec2i) -> anyhow::Result<BoardView> {
+	pub fn new(ctx: &mut toybox::Context, board_size: Vec2i, skin: Skin) -> anyhow::Result<BoardView> {
+		let bounds = Self::make_bounds(board_size);
+		let quad_buffer = QuadBuffer::new(&mut ctx.gfx, Self::num_quads(board_size));
+
 		let core = &mut ctx.gfx.core;
 		let rm = &mut ctx.gfx.resource_manager;
 
-		let bounds = Self::make_bounds(board_size);
-
 		Ok(BoardView {
 			main_vs: rm.request(gfx::LoadShaderRequest::from("shaders/main.vs.glsl")?),
 			main_fs: rm.request(gfx::LoadShaderRequest::from("shaders/main.fs.glsl")?),
-			atlas: rm.request(gfx::LoadImageRequest::from("images/atlas.png")),
-
 			sampler: {
 				let sampler = core.create_sampler();
 				core.set_sampler_addressing_mode(sampler, gfx::AddressingMode::Clamp);
@@ -49,55 +233,271 @@ impl BoardView {
 				sampler
 			},
 
+			skin,
+			palette_preset: PalettePreset::Skin,
+
 			bounds,
+			board_size,
 			cell_bounds: Self::make_cells(board_size, bounds),
 			hovered_cell: None,
+			hovered_sub_position: None,
+			cursor: None,
+			active_device: InputDevice::Mouse,
+			last_mouse_pos: None,
+			gap_mode: GapMode::Snap,
+			animations_enabled: true,
+			question_marks_enabled: true,
+
+			remote_cursors: Vec::new(),
+			flag_owners: Vec::new(),
+
+			draw_stats: DrawStats::default(),
+
+			quad_buffer,
+			cell_visuals: Map::new(board_size, None),
+			cell_animations: Map::new(board_size, None),
+			scratch_builder: QuadBuilder::default(),
 		})
 	}
 
 	pub fn reset(&mut self, board_size: Vec2i) {
+		self.board_size = board_size;
 		self.bounds = Self::make_bounds(board_size);
 		self.cell_bounds = Self::make_cells(board_size, self.bounds);
+		self.cell_visuals = Map::new(board_size, None);
+		self.cell_animations = Map::new(board_size, None);
+		self.cursor = self.cursor.map(|cursor| Vec2i::new(
+			cursor.x.clamp(0, board_size.x - 1),
+			cursor.y.clamp(0, board_size.y - 1),
+		));
 	}
 
-	pub fn update(&mut self, ctx: &mut toybox::Context, board: &Board, mouse_pos: Option<Vec2>) -> Option<(Vec2i, CellResponse)> {
-		// Find the first cell underneath the mouse, if any.
-		self.hovered_cell = mouse_pos.and_then(|mouse_pos|
-			self.cell_bounds.iter_with_positions()
-				.filter(|(_, bounds)| bounds.contains_point(mouse_pos))
-				.map(|(position, _)| position)
-				.next()
+	pub fn set_skin(&mut self, gfx: &mut gfx::System, skin: Skin) {
+		std::mem::replace(&mut self.skin, skin).destroy(gfx);
+		self.invalidate();
+	}
+
+	pub fn set_palette(&mut self, preset: PalettePreset) {
+		if preset != self.palette_preset {
+			self.palette_preset = preset;
+			self.invalidate();
+		}
+	}
+
+	/// Forces every cell to be rebuilt next draw.
+	pub fn invalidate(&mut self) {
+		self.cell_visuals = Map::new(self.board_size, None);
+	}
+
+	/// Pops in newly opened cells, staggered by their distance from wherever the reveal started.
+	pub fn animate_reveal(&mut self, time: f32, cells: impl IntoIterator<Item=(Vec2i, usize)>) {
+		if !self.animations_enabled {
+			return
+		}
+
+		for (position, distance) in cells {
+			self.cell_animations.set(position, Some(CellAnimation {
+				kind: AnimationKind::Reveal,
+				start_time: time + distance as f32 * REVEAL_STAGGER,
+				duration: REVEAL_DURATION,
+			}));
+		}
+	}
+
+	/// Reveals cells in a ripple expanding out from `origin`.
+	pub fn animate_ripple(&mut self, time: f32, origin: Vec2i, cells: impl IntoIterator<Item=Vec2i>) {
+		if !self.animations_enabled {
+			return
+		}
+
+		for position in cells {
+			let distance = (position - origin).to_vec2().length();
+
+			self.cell_animations.set(position, Some(CellAnimation {
+				kind: AnimationKind::Reveal,
+				start_time: time + distance * RIPPLE_STAGGER,
+				duration: REVEAL_DURATION,
+			}));
+		}
+	}
+
+	pub fn animate_flag(&mut self, time: f32, position: Vec2i) {
+		if !self.animations_enabled {
+			return
+		}
+
+		self.cell_animations.set(position, Some(CellAnimation {
+			kind: AnimationKind::FlagDrop,
+			start_time: time,
+			duration: FLAG_DROP_DURATION,
+		}));
+	}
+
+	pub fn cell_bounds(&self, position: Vec2i) -> Aabb2 {
+		*self.cell_bounds.get(position).unwrap()
+	}
+
+	pub fn atlas(&self) -> AtlasBinding {
+		AtlasBinding {
+			image: self.skin.atlas,
+			sampler: self.sampler,
+			uniforms: self.skin.atlas_uniforms(),
+			blank_glyph: self.skin.glyphs().blank,
+		}
+	}
+
+	pub fn pick_cell(&self, point: Vec2) -> Option<CellPick> {
+		if !self.bounds.contains_point(point) {
+			return None
+		}
+
+		let board_size = self.board_size.to_vec2();
+		let grid_position = (point - self.bounds.min) / self.bounds.size() * board_size;
+
+		// Points exactly on the max edge would otherwise land outside the board.
+		let position = Vec2i::new(
+			(grid_position.x.floor() as i32).clamp(0, self.board_size.x - 1),
+			(grid_position.y.floor() as i32).clamp(0, self.board_size.y - 1),
 		);
 
-		let Some(cell_position) = self.hovered_cell else { return None };
+		let margin = (1.0 - CELL_SCALE) / 2.0;
+		let sub_position = (grid_position - position.to_vec2() - Vec2::splat(margin)) / CELL_SCALE;
+
+		let in_gap = sub_position.x < 0.0 || sub_position.x > 1.0
+			|| sub_position.y < 0.0 || sub_position.y > 1.0;
 
-		let cell_state = *board.states.get(cell_position).unwrap();
-		if cell_state == CellState::Opened {
-			return None;
+		if in_gap && self.gap_mode == GapMode::Empty {
+			return None
 		}
 
-		if ctx.input.button_just_down(input::MouseButton::Right) {
-			let response = match cell_state {
-				CellState::Flagged => CellResponse::FlagRemoved,
-				CellState::Unopened => CellResponse::FlagPlaced,
-				_ => unreachable!()
-			};
+		Some(CellPick {
+			position,
+			sub_position: Vec2::new(sub_position.x.clamp(0.0, 1.0), sub_position.y.clamp(0.0, 1.0)),
+		})
+	}
 
-			return Some((cell_position, response))
+	/// The cell highlighted by whichever input device was used most recently.
+	pub fn highlighted_cell(&self) -> Option<Vec2i> {
+		match self.active_device {
+			InputDevice::Mouse => self.hovered_cell,
+			InputDevice::Keyboard | InputDevice::Gamepad => self.cursor,
+			InputDevice::Touch => None,
 		}
+	}
 
-		if cell_state == CellState::Unopened && ctx.input.button_just_down(input::MouseButton::Left) {
-			let cell_type = *board.types.get(cell_position).unwrap();
-			let response = match cell_type {
-				CellType::Bomb => CellResponse::BombHit,
-				CellType::Empty => CellResponse::OpenSpaceUncovered,
-				CellType::BombAdjacent(_) => CellResponse::UnsafeSpaceUncovered,
-			};
+	pub fn update(&mut self, ctx: &mut toybox::Context, board: &Board, mouse_pos: Option<Vec2>) -> Option<(Vec2i, CellResponse)> {
+		let pick = mouse_pos.and_then(|mouse_pos| self.pick_cell(mouse_pos));
+		self.hovered_cell = pick.map(|pick| pick.position);
+		self.hovered_sub_position = pick.map(|pick| pick.sub_position);
+
+		if mouse_pos != self.last_mouse_pos {
+			self.last_mouse_pos = mouse_pos;
+			self.active_device = InputDevice::Mouse;
+		}
+
+		if let Some(response) = self.update_keyboard(ctx, board) {
+			return Some(response)
+		}
+
+		let Some(cell_position) = self.hovered_cell else { return None };
+
+		let response = if ctx.input.button_just_down(input::MouseButton::Right) {
+			flag_response(board, cell_position, self.question_marks_enabled)
+		} else if ctx.input.button_just_down(input::MouseButton::Left) {
+			open_response(board, cell_position)
+				.or_else(|| chord_response(board, cell_position))
+		} else if ctx.input.button_just_down(input::MouseButton::Middle) {
+			chord_response(board, cell_position)
+		} else {
+			None
+		};
+
+		if response.is_some() {
+			self.active_device = InputDevice::Mouse;
+		}
+
+		response.map(|response| (cell_position, response))
+	}
+
+	fn update_keyboard(&mut self, ctx: &mut toybox::Context, board: &Board) -> Option<(Vec2i, CellResponse)> {
+		use input::Key;
+
+		let any_just_down = |keys: &[Key]| keys.iter().any(|&key| ctx.input.button_just_down(key));
+
+		let movement = [
+			(Vec2i::new( 0,  1), [Key::Up, Key::W, Key::K]),
+			(Vec2i::new( 0, -1), [Key::Down, Key::S, Key::J]),
+			(Vec2i::new(-1,  0), [Key::Left, Key::A, Key::H]),
+			(Vec2i::new( 1,  0), [Key::Right, Key::D, Key::L]),
+		];
+
+		let input = CursorInput {
+			movement: movement.into_iter()
+				.filter(|(_, keys)| any_just_down(keys))
+				.fold(Vec2i::zero(), |total, (delta, _)| total + delta),
+
+			jump_to_unopened: any_just_down(&[Key::Tab]),
+			open: any_just_down(&[Key::Space, Key::Return]),
+			flag: any_just_down(&[Key::F, Key::E]),
+			chord: any_just_down(&[Key::C, Key::Q]),
+		};
 
-			return Some((cell_position, response))
+		self.apply_cursor_input(InputDevice::Keyboard, board, input)
+	}
+
+	/// Opens or flags the cell under a touch. Opened cells are chorded instead.
+	pub fn apply_touch(&mut self, board: &Board, point: Vec2, flag: bool) -> Option<(Vec2i, CellResponse)> {
+		self.active_device = InputDevice::Touch;
+
+		let position = self.pick_cell(point)?.position;
+
+		let response = match flag {
+			true => flag_response(board, position, self.question_marks_enabled),
+			false => open_response(board, position),
+		};
+
+		response.or_else(|| chord_response(board, position))
+			.map(|response| (position, response))
+	}
+
+	/// Moves the cursor and performs any actions on the cell under it.
+	pub fn apply_cursor_input(&mut self, device: InputDevice, board: &Board, input: CursorInput) -> Option<(Vec2i, CellResponse)> {
+		if input.is_empty() {
+			return None
 		}
 
-		None
+		// Pick up from wherever the mouse was, so switching devices doesn't lose your place.
+		let cursor = match self.active_device {
+			InputDevice::Mouse => self.hovered_cell.or(self.cursor),
+			_ => self.cursor,
+		};
+
+		let cursor = cursor.unwrap_or(Vec2i::new(self.board_size.x / 2, self.board_size.y / 2));
+		let cursor = Vec2i::new(
+			(cursor.x + input.movement.x).clamp(0, self.board_size.x - 1),
+			(cursor.y + input.movement.y).clamp(0, self.board_size.y - 1),
+		);
+
+		let cursor = match input.jump_to_unopened {
+			true => next_unopened_cell(board, cursor).unwrap_or(cursor),
+			false => cursor,
+		};
+
+		self.cursor = Some(cursor);
+		self.active_device = device;
+
+		let response = if input.flag {
+			flag_response(board, cursor, self.question_marks_enabled)
+		} else if input.open {
+			open_response(board, cursor)
+				.or_else(|| chord_response(board, cursor))
+		} else if input.chord {
+			chord_response(board, cursor)
+		} else {
+			None
+		};
+
+		response.map(|response| (cursor, response))
 	}
 
 	fn make_bounds(board_size: Vec2i) -> Aabb2 {
@@ -112,33 +512,97 @@ impl BoardView {
 
 	fn make_cells(board_size: Vec2i, bounds: Aabb2) -> Map<Aabb2> {
 		Map::new_with(board_size, |pos| {
-			bounds.section(board_size, pos).scale_about_center(Vec2::splat(0.95))
+			bounds.section(board_size, pos).scale_about_center(Vec2::splat(CELL_SCALE))
 		})
 	}
 
 
-	pub fn draw(&self, gfx: &mut gfx::System, board: &Board) {
-		let mut builder = QuadBuilder::default();
+	fn num_quads(board_size: Vec2i) -> usize {
+		// One extra quad for the board background.
+		1 + (board_size.x * board_size.y) as usize * QUADS_PER_CELL
+	}
+
+	pub fn draw(&mut self, gfx: &mut gfx::System, board: &Board, time: f32) {
+		let start = Instant::now();
+		let mut stats = DrawStats::default();
 
-		builder.add(self.bounds, Color::grey(0.2), 0);
+		if self.skin.hot_reload(gfx) {
+			self.invalidate();
+		}
+
+		let num_quads = Self::num_quads(self.board_size);
+		if self.quad_buffer.num_quads() != num_quads {
+			self.quad_buffer.resize(gfx, num_quads);
+			self.cell_visuals = Map::new(self.board_size, None);
+		}
+
+		let builder = &mut self.scratch_builder;
+		let palette = Palette::new(self.palette_preset, self.skin.palette());
+		let style = CellStyle {
+			glyphs: self.skin.glyphs(),
+			palette: &palette,
+		};
+
+		if self.cell_visuals.iter().all(Option::is_none) {
+			builder.clear();
+			builder.add(self.bounds, palette.board, style.glyphs.blank);
+			self.quad_buffer.write(0, 1, builder);
+		}
 
+		let highlighted_cell = self.highlighted_cell();
 
 		let types_and_states = std::iter::zip(board.types.iter(), board.states.iter());
+		let cells = self.cell_bounds.iter_with_positions()
+			.zip(self.cell_visuals.iter_mut())
+			.zip(self.cell_animations.iter_mut());
+
+		for (cell_index, ((((position, cell_bounds), cached_visual), animation_slot), (&cell_type, &cell_state))) in cells.zip(types_and_states).enumerate() {
+			let animation = animation_slot.and_then(|animation| Some((animation.kind, animation.progress(time)?)));
+			if animation.is_none() {
+				*animation_slot = None;
+			}
+
+			let visual = CellVisual {
+				cell_type,
+				cell_state,
+				is_hovered: highlighted_cell == Some(position),
+				is_cursor: matches!(self.active_device, InputDevice::Keyboard | InputDevice::Gamepad) && self.cursor == Some(position),
+				is_detonated: board.detonated == Some(position),
+				is_wrong_flag: board.is_wrong_flag(position),
+				remote_cursor: self.remote_cursors.iter()
+					.find(|&&(cursor, _)| cursor == position)
+					.map(|&(_, player_index)| player_index),
+				flag_owner: self.flag_owners.iter()
+					.find(|&&(flag, _)| flag == position)
+					.map(|&(_, player_index)| player_index),
+				animation,
+			};
+
+			if *cached_visual == Some(visual) {
+				continue
+			}
+
+			*cached_visual = Some(visual);
 
-		for ((position, cell_bounds), (cell_type, cell_state)) in self.cell_bounds.iter_with_positions().zip(types_and_states) {
-			let is_hovered = self.hovered_cell == Some(position);
-			draw_cell(&mut builder, *cell_bounds, *cell_type, *cell_state, is_hovered);
+			builder.clear();
+			draw_cell(builder, &style, *cell_bounds, visual);
+
+			self.quad_buffer.write(1 + cell_index * QUADS_PER_CELL, QUADS_PER_CELL, builder);
+			stats.cells_rewritten += 1;
 		}
 
-		builder.finish();
+		stats.quads_uploaded = self.quad_buffer.flush(gfx);
+		stats.build_time = start.elapsed();
+		self.draw_stats = stats;
 
 		let mut group = gfx.frame_encoder.command_group("main");
 
 		group.draw(self.main_vs, self.main_fs)
-			.elements(builder.indices.len() as u32)
-			.indexed(&builder.indices)
-			.ssbo(0, &builder.vertices)
-			.sampled_image(0, self.atlas, self.sampler)
+			.elements(self.quad_buffer.num_elements())
+			.indexed(self.quad_buffer.index_buffer())
+			.ssbo(0, self.quad_buffer.vertex_buffer())
+			.ubo(1, &[self.skin.atlas_uniforms()])
+			.sampled_image(0, self.skin.atlas, self.sampler)
 			.depth_test(false)
 			.blend_mode(gfx::BlendMode::ALPHA);
 	}
@@ -149,38 +613,147 @@ impl BoardView {
 
 
 
-fn draw_cell(builder: &mut QuadBuilder, bounds: Aabb2, cell_type: CellType, cell_state: CellState, is_hovered: bool) {
-	match cell_state {
-		CellState::Unopened => draw_cell_unopened(builder, bounds, is_hovered),
-		CellState::Flagged => draw_cell_flag(builder, bounds, is_hovered),
-		CellState::Opened => draw_cell_opened(builder, bounds, cell_type),
+/// Finds the next unopened cell after `start` in reading order, wrapping around the board.
+fn next_unopened_cell(board: &Board, start: Vec2i) -> Option<Vec2i> {
+	let size = board.size();
+	let num_cells = (size.x * size.y) as usize;
+	let start_index = (start.x + start.y * size.x) as usize;
+
+	(1..=num_cells)
+		.map(|offset| (start_index + offset) % num_cells)
+		.map(|index| Vec2i::new(index as i32 % size.x, index as i32 / size.x))
+		.find(|&position| board.states.get(position) == Some(&CellState::Unopened))
+}
+
+
+struct CellStyle<'a> {
+	glyphs: &'a SkinGlyphs,
+	palette: &'a Palette,
+}
+
+
+fn draw_cell(builder: &mut QuadBuilder, style: &CellStyle, bounds: Aabb2, visual: CellVisual) {
+	let CellVisual{cell_type, cell_state, is_hovered, is_cursor, is_detonated, is_wrong_flag, remote_cursor, flag_owner, animation} = visual;
+	let CellStyle{glyphs, palette} = style;
+
+	let flag_color = flag_owner.map_or(palette.glyph, player_color);
+
+	if is_detonated {
+		builder.add(bounds, palette.detonated, glyphs.blank);
+	}
+
+	if is_wrong_flag {
+		draw_cell_unopened(builder, style, bounds, false);
+		builder.add(bounds, palette.glyph, glyphs.wrong_flag);
+		builder.add(bounds, palette.wrong_outline, glyphs.outline);
+		return
+	}
+
+	match (cell_state, animation) {
+		(CellState::Unopened, _) => draw_cell_unopened(builder, style, bounds, is_hovered),
+
+		(CellState::Flagged, Some((AnimationKind::FlagDrop, progress))) => {
+			draw_cell_unopened(builder, style, bounds, is_hovered);
+
+			let drop_height = bounds.size().y * 0.6 * (1.0 - ease_out_bounce(progress));
+			let alpha = (progress * 4.0).min(1.0);
+			builder.add(bounds.translate(Vec2::new(0.0, drop_height)), flag_color.with_alpha(alpha), glyphs.flag);
+		}
+
+		(CellState::Flagged, _) => draw_cell_flag(builder, style, bounds, flag_color, is_hovered),
+
+		(CellState::Questioned, _) => {
+			draw_cell_unopened(builder, style, bounds, is_hovered);
+			builder.add(bounds, palette.glyph, glyphs.question);
+		}
+
+		(CellState::Opened, Some((AnimationKind::Reveal, progress))) => {
+			let cover_bounds = bounds.scale_about_center(Vec2::splat(1.0 - progress));
+			builder.add(cover_bounds, palette.unopened.with_alpha(1.0 - progress), glyphs.blank);
+
+			if progress > 0.0 {
+				let glyph_bounds = bounds.scale_about_center(Vec2::splat(ease_out_back(progress)));
+				draw_cell_opened(builder, style, glyph_bounds, cell_type, progress);
+			}
+		}
+
+		(CellState::Opened, _) => draw_cell_opened(builder, style, bounds, cell_type, 1.0),
+	}
+
+	if is_cursor {
+		let outline = palette.hover_outline.unwrap_or(palette.glyph);
+		builder.add(bounds, outline, glyphs.outline);
+
+	} else if is_hovered && cell_state != CellState::Opened {
+		if let Some(outline) = palette.hover_outline {
+			builder.add(bounds, outline, glyphs.outline);
+		}
+	}
+
+	if let Some(player_index) = remote_cursor {
+		let outline_bounds = bounds.scale_about_center(Vec2::splat(0.85));
+		builder.add(outline_bounds, player_color(player_index), glyphs.outline);
 	}
 }
 
-fn draw_cell_unopened(builder: &mut QuadBuilder, bounds: Aabb2, is_hovered: bool) {
+fn draw_cell_unopened(builder: &mut QuadBuilder, style: &CellStyle, bounds: Aabb2, is_hovered: bool) {
 	let bg_color = match is_hovered {
-		false => Color::grey(0.3),
-		true => Color::grey(0.7),
+		false => style.palette.unopened,
+		true => style.palette.hovered,
 	};
 
-	builder.add(bounds, bg_color, 0);
+	builder.add(bounds, bg_color, style.glyphs.blank);
 }
 
-fn draw_cell_flag(builder: &mut QuadBuilder, bounds: Aabb2, is_hovered: bool) {
-	draw_cell_unopened(builder, bounds, is_hovered);
-	builder.add(bounds, Color::white(), 9);
+fn draw_cell_flag(builder: &mut QuadBuilder, style: &CellStyle, bounds: Aabb2, color: Color, is_hovered: bool) {
+	draw_cell_unopened(builder, style, bounds, is_hovered);
+
+	if let Some(pattern) = style.palette.flag_pattern {
+		builder.add(bounds, pattern.with_alpha(0.5), style.glyphs.hatch);
+	}
+
+	builder.add(bounds, color, style.glyphs.flag);
 }
 
-fn draw_cell_opened(builder: &mut QuadBuilder, bounds: Aabb2, cell_type: CellType) {
+fn draw_cell_opened(builder: &mut QuadBuilder, style: &CellStyle, bounds: Aabb2, cell_type: CellType, alpha: f32) {
+	let CellStyle{glyphs, palette} = style;
+
 	match cell_type {
 		CellType::Empty => {},
 
 		CellType::Bomb => {
-			builder.add(bounds, Color::white(), 10);
+			builder.add(bounds, palette.glyph.with_alpha(alpha), glyphs.mine);
 		}
 
-		CellType::BombAdjacent(count) => {
-			builder.add(bounds, Color::white(), count as u16);
+		CellType::BombAdjacent(count) => match palette.digits {
+			Some(digits) => builder.add(bounds, digits[count.clamp(1, 8) - 1].with_alpha(alpha), glyphs.mono_digit(count)),
+			None => builder.add(bounds, palette.glyph.with_alpha(alpha), glyphs.digit(count)),
 		}
 	}
-}
\ No newline at end of file
+}
+
+
+
+fn ease_out_back(t: f32) -> f32 {
+	let c1 = 1.70158;
+	let c3 = c1 + 1.0;
+	1.0 + c3 * (t - 1.0).powi(3) + c1 * (t - 1.0).powi(2)
+}
+
+fn ease_out_bounce(t: f32) -> f32 {
+	let n1 = 7.5625;
+	let d1 = 2.75;
+
+	if t < 1.0 / d1 {
+		n1 * t * t
+	} else if t < 2.0 / d1 {
+		let t = t - 1.5 / d1;
+		n1 * t * t + 0.75
+	} else if t < 2.5 / d1 {
+		let t = t - 2.25 / d1;
+		n1 * t * t + 0.9375
+	} else {
+		let t = t - 2.625 / d1;
+		n1 * t * t + 0.984375
+	}
+}
@@ -0,0 +1,140 @@
+use std::io::Write;
+use std::path::Path;
+
+
+/// Writes interleaved samples as a 16 bit PCM WAV file. Samples are clamped to [-1, 1].
+pub fn write_wav(path: &Path, sample_rate: u32, channels: u16, samples: &[f32]) -> anyhow::Result<()> {
+	const BYTES_PER_SAMPLE: u16 = 2;
+
+	let data_size = (samples.len() * BYTES_PER_SAMPLE as usize) as u32;
+	let block_align = channels * BYTES_PER_SAMPLE;
+	let byte_rate = sample_rate * block_align as u32;
+
+	let mut bytes = Vec::with_capacity(44 + data_size as usize);
+
+	bytes.extend_from_slice(b"RIFF");
+	bytes.extend_from_slice(&(36 + data_size).to_le_bytes());
+	bytes.extend_from_slice(b"WAVE");
+
+	bytes.extend_from_slice(b"fmt ");
+	bytes.extend_from_slice(&16u32.to_le_bytes());
+	bytes.extend_from_slice(&1u16.to_le_bytes()); // PCM
+	bytes.extend_from_slice(&channels.to_le_bytes());
+	bytes.extend_from_slice(&sample_rate.to_le_bytes());
+	bytes.extend_from_slice(&byte_rate.to_le_bytes());
+	bytes.extend_from_slice(&block_align.to_le_bytes());
+	bytes.extend_from_slice(&(BYTES_PER_SAMPLE * 8).to_le_bytes());
+
+	bytes.extend_from_slice(b"data");
+	bytes.extend_from_slice(&data_size.to_le_bytes());
+
+	for &sample in samples {
+		let quantized = (sample.clamp(-1.0, 1.0) * i16::MAX as f32).round() as i16;
+		bytes.extend_from_slice(&quantized.to_le_bytes());
+	}
+
+	std::fs::File::create(path)?.write_all(&bytes)?;
+
+	Ok(())
+}
+
+
+/// Decoded contents of a WAV file, as interleaved samples in [-1, 1].
+pub struct WavData {
+	pub sample_rate: u32,
+	pub channels: u16,
+	pub samples: Vec<f32>,
+}
+
+/// Reads an uncompressed WAV file. Supports 8, 16, 24 and 32 bit integer PCM, and 32 bit float.
+pub fn read_wav(bytes: &[u8]) -> anyhow::Result<WavData> {
+	anyhow::ensure!(bytes.len() >= 12 && &bytes[0..4] == b"RIFF" && &bytes[8..12] == b"WAVE", "Not a WAV file");
+
+	let mut format = None;
+	let mut data = None;
+
+	let mut chunks = &bytes[12..];
+	while chunks.len() >= 8 {
+		let id = &chunks[0..4];
+		let size = u32::from_le_bytes(chunks[4..8].try_into()?) as usize;
+		let body = chunks.get(8..8 + size).ok_or_else(|| anyhow::anyhow!("Truncated '{}' chunk", String::from_utf8_lossy(id)))?;
+
+		match id {
+			b"fmt " => {
+				anyhow::ensure!(body.len() >= 16, "Malformed fmt chunk");
+				let mut tag = u16::from_le_bytes([body[0], body[1]]);
+				let channels = u16::from_le_bytes([body[2], body[3]]);
+				let sample_rate = u32::from_le_bytes(body[4..8].try_into()?);
+				let bits = u16::from_le_bytes([body[14], body[15]]);
+
+				// WAVE_FORMAT_EXTENSIBLE keeps the real format at the start of its subformat GUID.
+				if tag == 0xFFFE && body.len() >= 26 {
+					tag = u16::from_le_bytes([body[24], body[25]]);
+				}
+				format = Some((tag, channels, sample_rate, bits));
+			}
+
+			b"data" => data = Some(body),
+			_ => {}
+		}
+
+		// Chunks are padded to an even size.
+		let advance = (8 + size + (size & 1)).min(chunks.len());
+		chunks = &chunks[advance..];
+	}
+
+	let (tag, channels, sample_rate, bits) = format.ok_or_else(|| anyhow::anyhow!("Missing fmt chunk"))?;
+	let data = data.ok_or_else(|| anyhow::anyhow!("Missing data chunk"))?;
+
+	anyhow::ensure!(channels > 0, "WAV file has no channels");
+
+	let is_float = match tag {
+		1 => false,
+		3 => true,
+		_ => anyhow::bail!("Unsupported WAV format {tag:#x}"),
+	};
+
+	let samples = match (is_float, bits) {
+		(true, 32) => data.array_chunks().map(|&bytes| f32::from_le_bytes(bytes)).collect(),
+		(false, 8) => data.iter().map(|&byte| (byte as f32 - 128.0) / 128.0).collect(),
+		(false, 16) => data.array_chunks().map(|&bytes| i16::from_le_bytes(bytes) as f32 / 32768.0).collect(),
+		(false, 24) => data.array_chunks().map(|&[a, b, c]| i32::from_le_bytes([0, a, b, c]) as f32 / 2147483648.0).collect(),
+		(false, 32) => data.array_chunks().map(|&bytes| i32::from_le_bytes(bytes) as f32 / 2147483648.0).collect(),
+		_ => anyhow::bail!("Unsupported WAV bit depth {bits}"),
+	};
+
+	Ok(WavData {
+		sample_rate,
+		channels,
+		samples,
+	})
+}
+
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn write_then_read() {
+		let samples: Vec<f32> = (0..200)
+			.map(|index| (index as f32 * 0.1).sin() * 1.2)
+			.collect();
+
+		let path = std::env::temp_dir().join(format!("minesweeper-wav-test-{}.wav", std::process::id()));
+		write_wav(&path, 22050, 2, &samples).unwrap();
+
+		let bytes = std::fs::read(&path);
+		std::fs::remove_file(&path).ok();
+
+		let wav = read_wav(&bytes.unwrap()).unwrap();
+		assert_eq!(wav.sample_rate, 22050);
+		assert_eq!(wav.channels, 2);
+		assert_eq!(wav.samples.len(), samples.len());
+
+		// Out of range samples are clamped, and everything else is only off by quantization to 16 bits.
+		for (&read, &written) in wav.samples.iter().zip(samples.iter()) {
+			assert!((read - written.clamp(-1.0, 1.0)).abs() <= 2.0 / 32768.0, "wrote {written}, read {read}");
+		}
+	}
+}